@@ -0,0 +1,76 @@
+use crate::{config::AuthConfig, error::ApiError};
+use axum::{
+    extract::{Request, State},
+    http::{
+        HeaderValue,
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use reggy_core::registry_error::RegistryError;
+use std::sync::Arc;
+
+// Every route, including the `/v2/` version check, requires HTTP basic credentials
+// so clients discover the challenge before attempting a push.
+pub async fn authenticate(
+    State(auth): State<Arc<AuthConfig>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let credentials = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "))
+        .and_then(|v| STANDARD.decode(v).ok())
+        .and_then(|v| String::from_utf8(v).ok());
+    // bcrypt is deliberately slow, so it is kept off the async workers.
+    let users = auth.clone();
+    let authorised = tokio::task::spawn_blocking(move || {
+        credentials
+            .as_deref()
+            .and_then(|c| c.split_once(':'))
+            .is_some_and(|(username, password)| verify(&users, username, password))
+    })
+    .await
+    .unwrap_or(false);
+    if authorised {
+        return next.run(req).await;
+    }
+
+    let mut response = ApiError(RegistryError::Unauthorised).into_response();
+    if let Ok(challenge) = HeaderValue::try_from(format!("Basic realm=\"{}\"", auth.realm)) {
+        response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+    }
+    response
+}
+
+fn verify(auth: &AuthConfig, username: &str, password: &str) -> bool {
+    auth.users
+        .iter()
+        .filter(|u| u.username == username)
+        .any(|u| bcrypt::verify(password, &u.password_hash).unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserConfig;
+
+    #[test]
+    fn verifies_password_hashes() {
+        let auth = AuthConfig {
+            realm: "reggy".to_string(),
+            users: vec![UserConfig {
+                username: "ci".to_string(),
+                password_hash: bcrypt::hash("secret", 4).unwrap(),
+            }],
+        };
+
+        assert!(verify(&auth, "ci", "secret"));
+        assert!(!verify(&auth, "ci", "guess"));
+        assert!(!verify(&auth, "admin", "secret"));
+    }
+}
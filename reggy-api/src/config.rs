@@ -0,0 +1,603 @@
+use clap::Parser;
+use reggy_core::storage::Storage;
+use reggy_fs::FsStore;
+use reggy_mem::MemStore;
+use reggy_s3::S3Store;
+use serde::Deserialize;
+use std::{fmt, fs, path::PathBuf, sync::Arc, time::Duration};
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_ROOT_DIR: &str = "registry";
+
+/// Settings are read from the config file, then overridden by environment
+/// variables and finally by command line flags.
+#[derive(Parser, Debug, Default)]
+#[command(version, about = "An OCI distribution registry")]
+pub struct Args {
+    /// Path to a TOML or YAML config file.
+    #[arg(short, long, env = "REGGY_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "REGGY_HOST")]
+    pub host: Option<String>,
+    #[arg(long, env = "REGGY_PORT")]
+    pub port: Option<u16>,
+    /// Hostname clients use to reach the registry, defaults to the host.
+    #[arg(long, env = "REGGY_HOSTNAME")]
+    pub hostname: Option<String>,
+    /// Switches storage backend, keeping the file's options for the same backend.
+    #[arg(long, env = "REGGY_STORAGE_BACKEND", value_parser = ["filesystem", "memory", "s3"])]
+    pub storage_backend: Option<String>,
+    /// Serves from the filesystem backend rooted at this directory, unless
+    /// another backend is chosen.
+    #[arg(long, env = "REGGY_ROOT_DIR")]
+    pub root_dir: Option<String>,
+    /// Caps the blobs the memory backend holds.
+    #[arg(long, env = "REGGY_MAX_BLOB_BYTES")]
+    pub max_blob_bytes: Option<usize>,
+    #[arg(long, env = "REGGY_S3_BUCKET")]
+    pub s3_bucket: Option<String>,
+    #[arg(long, env = "REGGY_S3_PREFIX")]
+    pub s3_prefix: Option<String>,
+    #[arg(long, env = "REGGY_S3_ENDPOINT")]
+    pub s3_endpoint: Option<String>,
+    #[arg(long, env = "REGGY_S3_REGION")]
+    pub s3_region: Option<String>,
+    /// Replaces the configured users with those of an htpasswd file of bcrypt
+    /// hashes, e.g. from `htpasswd -cbB <file> <user> <password>`.
+    #[arg(long, env = "REGGY_HTPASSWD")]
+    pub htpasswd: Option<PathBuf>,
+    #[arg(long, env = "REGGY_TLS_CERTIFICATE", requires = "tls_key")]
+    pub tls_certificate: Option<PathBuf>,
+    #[arg(long, env = "REGGY_TLS_KEY", requires = "tls_certificate")]
+    pub tls_key: Option<PathBuf>,
+    #[arg(long, env = "REGGY_MINIMUM_CHUNK_LENGTH")]
+    pub minimum_chunk_length: Option<usize>,
+    #[arg(long, env = "REGGY_UPLOAD_SESSION_TTL_SECS")]
+    pub upload_session_ttl_secs: Option<u64>,
+    #[arg(long, env = "REGGY_MAX_MANIFEST_BYTES")]
+    pub max_manifest_bytes: Option<usize>,
+    #[arg(long, env = "REGGY_REFERRERS_TAG_SCHEMA")]
+    pub referrers_tag_schema: Option<bool>,
+    /// A tracing filter such as `info` or `reggy_api=debug`.
+    #[arg(long, env = "REGGY_LOG_LEVEL")]
+    pub log_level: Option<String>,
+    #[arg(long, env = "REGGY_LOG_FORMAT", value_parser = ["text", "json"])]
+    pub log_format: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub auth: Option<AuthConfig>,
+    pub tls: Option<TlsConfig>,
+    pub limits: LimitsConfig,
+    pub referrers_tag_schema: bool,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub hostname: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "backend", rename_all = "lowercase", deny_unknown_fields)]
+pub enum StorageConfig {
+    Filesystem {
+        root_dir: String,
+    },
+    /// Keeps everything in memory, so content is lost on restart.
+    /// `max_blob_bytes` caps committed blobs only, uploads in progress and
+    /// manifests are held in full on top of it.
+    Memory {
+        max_blob_bytes: Option<usize>,
+    },
+    /// Credentials are read from the standard AWS sources. `endpoint` points
+    /// at S3-compatible services such as MinIO.
+    S3 {
+        bucket: String,
+        #[serde(default)]
+        prefix: String,
+        endpoint: Option<String>,
+        region: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    #[serde(default = "default_realm")]
+    pub realm: String,
+    pub users: Vec<UserConfig>,
+}
+
+/// Passwords are stored as bcrypt hashes, e.g. from `htpasswd -nbB user password`.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    pub username: String,
+    pub password_hash: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub minimum_chunk_length: Option<usize>,
+    pub upload_session_ttl_secs: u64,
+    pub max_manifest_bytes: usize,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub format: LogFormat,
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 3000,
+            hostname: None,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig::Filesystem {
+            root_dir: DEFAULT_ROOT_DIR.to_string(),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            minimum_chunk_length: None,
+            upload_session_ttl_secs: 24 * 60 * 60,
+            // The distribution spec expects registries to accept manifests of at least 4 MiB.
+            max_manifest_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl StorageConfig {
+    fn backend(&self) -> &'static str {
+        match self {
+            StorageConfig::Filesystem { .. } => "filesystem",
+            StorageConfig::Memory { .. } => "memory",
+            StorageConfig::S3 { .. } => "s3",
+        }
+    }
+
+    pub async fn open(&self) -> Arc<dyn Storage> {
+        match self {
+            StorageConfig::Filesystem { root_dir } => Arc::new(FsStore {
+                root_dir: root_dir.clone(),
+            }),
+            StorageConfig::Memory { max_blob_bytes } => match max_blob_bytes {
+                Some(max_blob_bytes) => Arc::new(MemStore::with_max_blob_bytes(*max_blob_bytes)),
+                None => Arc::new(MemStore::new()),
+            },
+            StorageConfig::S3 {
+                bucket,
+                prefix,
+                endpoint,
+                region,
+            } => Arc::new(
+                S3Store::connect(bucket, prefix, endpoint.as_deref(), region.as_deref()).await,
+            ),
+        }
+    }
+}
+
+fn default_realm() -> String {
+    "reggy".to_string()
+}
+
+// Each line holds `username:hash`, blank lines and `#` comments are skipped.
+fn read_htpasswd(path: &PathBuf) -> Result<Vec<UserConfig>, ConfigError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| ConfigError(format!("Cannot read '{}': {}.", path.display(), e)))?;
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once(':') {
+            Some((username, password_hash)) => Ok(UserConfig {
+                username: username.to_string(),
+                password_hash: password_hash.to_string(),
+            }),
+            None => Err(ConfigError(format!(
+                "Invalid htpasswd file '{}': expected 'username:hash' lines.",
+                path.display()
+            ))),
+        })
+        .collect()
+}
+
+impl Config {
+    pub fn load(args: Args) -> Result<Self, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => Self::read(path)?,
+            None => Config::default(),
+        };
+        config.apply(args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn read(path: &PathBuf) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| ConfigError(format!("Cannot read '{}': {}.", path.display(), e)))?;
+        let parsed = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&content).map_err(|e| e.to_string()),
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content).map_err(|e| e.to_string()),
+            _ => Err("expected a .toml, .yaml or .yml file".to_string()),
+        };
+        parsed.map_err(|e| ConfigError(format!("Invalid config file '{}': {}", path.display(), e)))
+    }
+
+    fn apply(&mut self, args: Args) -> Result<(), ConfigError> {
+        self.apply_storage(&args);
+        if let Some(host) = args.host {
+            self.server.host = host;
+        }
+        if let Some(port) = args.port {
+            self.server.port = port;
+        }
+        if let Some(hostname) = args.hostname {
+            self.server.hostname = Some(hostname);
+        }
+        if let Some(path) = &args.htpasswd {
+            let users = read_htpasswd(path)?;
+            match &mut self.auth {
+                Some(auth) => auth.users = users,
+                None => {
+                    self.auth = Some(AuthConfig {
+                        realm: default_realm(),
+                        users,
+                    })
+                }
+            }
+        }
+        if let (Some(certificate), Some(key)) = (args.tls_certificate, args.tls_key) {
+            self.tls = Some(TlsConfig { certificate, key });
+        }
+        if let Some(min) = args.minimum_chunk_length {
+            self.limits.minimum_chunk_length = Some(min);
+        }
+        if let Some(ttl) = args.upload_session_ttl_secs {
+            self.limits.upload_session_ttl_secs = ttl;
+        }
+        if let Some(max) = args.max_manifest_bytes {
+            self.limits.max_manifest_bytes = max;
+        }
+        if let Some(referrers_tag_schema) = args.referrers_tag_schema {
+            self.referrers_tag_schema = referrers_tag_schema;
+        }
+        if let Some(level) = args.log_level {
+            self.logging.level = level;
+        }
+        match args.log_format.as_deref() {
+            Some("json") => self.logging.format = LogFormat::Json,
+            Some(_) => self.logging.format = LogFormat::Text,
+            None => {}
+        }
+        Ok(())
+    }
+
+    // Options are taken from the flags first, then from the file when it
+    // configures the same backend.
+    fn apply_storage(&mut self, args: &Args) {
+        let backend = match (&args.storage_backend, &args.root_dir) {
+            (Some(backend), _) => backend.as_str(),
+            (None, Some(_)) => "filesystem",
+            (None, None) => self.storage.backend(),
+        };
+
+        self.storage = match (backend, &self.storage) {
+            ("memory", current) => StorageConfig::Memory {
+                max_blob_bytes: args.max_blob_bytes.or(match current {
+                    StorageConfig::Memory { max_blob_bytes } => *max_blob_bytes,
+                    _ => None,
+                }),
+            },
+            (
+                "s3",
+                StorageConfig::S3 {
+                    bucket,
+                    prefix,
+                    endpoint,
+                    region,
+                },
+            ) => StorageConfig::S3 {
+                bucket: args.s3_bucket.clone().unwrap_or(bucket.clone()),
+                prefix: args.s3_prefix.clone().unwrap_or(prefix.clone()),
+                endpoint: args.s3_endpoint.clone().or(endpoint.clone()),
+                region: args.s3_region.clone().or(region.clone()),
+            },
+            ("s3", _) => StorageConfig::S3 {
+                bucket: args.s3_bucket.clone().unwrap_or_default(),
+                prefix: args.s3_prefix.clone().unwrap_or_default(),
+                endpoint: args.s3_endpoint.clone(),
+                region: args.s3_region.clone(),
+            },
+            (_, current) => StorageConfig::Filesystem {
+                root_dir: args.root_dir.clone().unwrap_or(match current {
+                    StorageConfig::Filesystem { root_dir } => root_dir.clone(),
+                    _ => DEFAULT_ROOT_DIR.to_string(),
+                }),
+            },
+        };
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.server.host.is_empty() {
+            return Err(ConfigError("server.host must not be empty.".to_string()));
+        }
+        match &self.storage {
+            StorageConfig::Filesystem { root_dir } if root_dir.is_empty() => {
+                return Err(ConfigError(
+                    "storage.root_dir must not be empty.".to_string(),
+                ));
+            }
+            StorageConfig::Memory {
+                max_blob_bytes: Some(0),
+            } => {
+                return Err(ConfigError(
+                    "storage.max_blob_bytes must be greater than 0.".to_string(),
+                ));
+            }
+            StorageConfig::S3 { bucket, .. } if bucket.is_empty() => {
+                return Err(ConfigError("storage.bucket must not be empty.".to_string()));
+            }
+            StorageConfig::Filesystem { .. }
+            | StorageConfig::Memory { .. }
+            | StorageConfig::S3 { .. } => {}
+        }
+        if let Some(auth) = &self.auth {
+            if auth.users.is_empty() {
+                return Err(ConfigError(
+                    "auth.users must list at least one user.".to_string(),
+                ));
+            }
+            if let Some(user) = auth
+                .users
+                .iter()
+                .find(|u| u.password_hash.parse::<bcrypt::HashParts>().is_err())
+            {
+                return Err(ConfigError(format!(
+                    "auth.users: password_hash of '{}' must be a bcrypt hash.",
+                    user.username
+                )));
+            }
+        }
+        if let Some(tls) = &self.tls {
+            for path in [&tls.certificate, &tls.key] {
+                if !path.is_file() {
+                    return Err(ConfigError(format!(
+                        "TLS file '{}' does not exist.",
+                        path.display()
+                    )));
+                }
+            }
+        }
+        if self.limits.minimum_chunk_length == Some(0) {
+            return Err(ConfigError(
+                "limits.minimum_chunk_length must be greater than 0.".to_string(),
+            ));
+        }
+        if self.limits.upload_session_ttl_secs == 0 {
+            return Err(ConfigError(
+                "limits.upload_session_ttl_secs must be greater than 0.".to_string(),
+            ));
+        }
+        if self.limits.max_manifest_bytes == 0 {
+            return Err(ConfigError(
+                "limits.max_manifest_bytes must be greater than 0.".to_string(),
+            ));
+        }
+        self.log_filter()?;
+        Ok(())
+    }
+
+    pub fn hostname(&self) -> &str {
+        self.server.hostname.as_deref().unwrap_or(&self.server.host)
+    }
+
+    pub fn upload_session_ttl(&self) -> Duration {
+        Duration::from_secs(self.limits.upload_session_ttl_secs)
+    }
+
+    pub fn log_filter(&self) -> Result<EnvFilter, ConfigError> {
+        EnvFilter::try_new(&self.logging.level).map_err(|e| {
+            ConfigError(format!(
+                "logging.level '{}' is not a valid filter: {}.",
+                self.logging.level, e
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn config_file(extension: &str, content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new()
+            .suffix(extension)
+            .tempfile()
+            .unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    fn args(file: &tempfile::NamedTempFile) -> Args {
+        Args {
+            config: Some(file.path().to_path_buf()),
+            ..Args::default()
+        }
+    }
+
+    #[test]
+    fn reads_toml_and_yaml() {
+        let toml = config_file(
+            ".toml",
+            r#"
+            [server]
+            port = 5000
+            [storage]
+            backend = "filesystem"
+            root_dir = "/data"
+            [limits]
+            minimum_chunk_length = 1024
+            "#,
+        );
+        let yaml = config_file(
+            ".yaml",
+            "server:\n  port: 5000\nstorage:\n  backend: filesystem\n  root_dir: /data\nlimits:\n  minimum_chunk_length: 1024\n",
+        );
+
+        for file in [toml, yaml] {
+            let config = Config::load(args(&file)).unwrap();
+            assert_eq!(config.server.port, 5000);
+            assert_eq!(config.hostname(), "localhost");
+            assert_eq!(config.limits.minimum_chunk_length, Some(1024));
+            assert!(matches!(
+                config.storage,
+                StorageConfig::Filesystem { root_dir } if root_dir == "/data"
+            ));
+        }
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let file = config_file(
+            ".toml",
+            r#"
+            [server]
+            port = 5000
+            [storage]
+            backend = "s3"
+            bucket = "reggy"
+            region = "eu-west-1"
+            [auth]
+            realm = "team"
+            users = [{ username = "ci", password_hash = "$2b$12$TNmHvXrnpiikifiAVAbuOedCucRuQrW1KrcAecpHAh1jO/z82FhAS" }]
+            "#,
+        );
+        let htpasswd = config_file(
+            ".htpasswd",
+            "# users\nops:$2b$12$TNmHvXrnpiikifiAVAbuOedCucRuQrW1KrcAecpHAh1jO/z82FhAS\n",
+        );
+        let args = Args {
+            port: Some(6000),
+            log_format: Some("json".to_string()),
+            s3_prefix: Some("registry".to_string()),
+            htpasswd: Some(htpasswd.path().to_path_buf()),
+            ..args(&file)
+        };
+
+        let config = Config::load(args).unwrap();
+        assert_eq!(config.server.port, 6000);
+        assert_eq!(config.logging.format, LogFormat::Json);
+        assert!(matches!(
+            config.storage,
+            StorageConfig::S3 { bucket, prefix, region: Some(region), .. }
+                if bucket == "reggy" && prefix == "registry" && region == "eu-west-1"
+        ));
+        let auth = config.auth.unwrap();
+        assert_eq!(auth.realm, "team");
+        assert_eq!(auth.users.len(), 1);
+        assert_eq!(auth.users[0].username, "ops");
+    }
+
+    #[test]
+    fn flags_switch_storage_backend() {
+        let file = config_file(".toml", "[storage]\nbackend = \"s3\"\nbucket = \"reggy\"\n");
+        let memory = Args {
+            storage_backend: Some("memory".to_string()),
+            max_blob_bytes: Some(1024),
+            ..args(&file)
+        };
+        let filesystem = Args {
+            root_dir: Some("/data".to_string()),
+            ..args(&file)
+        };
+        let s3 = Args {
+            storage_backend: Some("s3".to_string()),
+            ..Args::default()
+        };
+
+        let config = Config::load(memory).unwrap();
+        assert!(matches!(
+            config.storage,
+            StorageConfig::Memory {
+                max_blob_bytes: Some(1024)
+            }
+        ));
+        let config = Config::load(filesystem).unwrap();
+        assert!(matches!(
+            config.storage,
+            StorageConfig::Filesystem { root_dir } if root_dir == "/data"
+        ));
+        // S3 needs a bucket, which neither the flags nor the defaults give.
+        assert!(Config::load(s3).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        let unknown = config_file(".toml", "[server]\nprot = 5000\n");
+        let ttl = config_file(".toml", "[limits]\nupload_session_ttl_secs = 0\n");
+        let users = config_file(
+            ".toml",
+            "[auth]\nusers = [{ username = \"ci\", password_hash = \"secret\" }]\n",
+        );
+
+        for file in [unknown, ttl, users] {
+            assert!(Config::load(args(&file)).is_err());
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use axum::{
+    http::{HeaderValue, StatusCode, header::RANGE},
+    response::{IntoResponse, Response},
+};
+use reggy_core::registry_error::RegistryError;
+use serde::Serialize;
+
+pub struct ApiError(pub RegistryError);
+
+impl From<RegistryError> for ApiError {
+    fn from(err: RegistryError) -> Self {
+        Self(err)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    errors: Vec<ErrorInfo>,
+}
+
+#[derive(Serialize)]
+struct ErrorInfo {
+    code: String,
+    message: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self.0 {
+            RegistryError::BlobUnknown
+            | RegistryError::BlobUploadUnknown
+            | RegistryError::ManifestUnknown
+            | RegistryError::RepositoryNameUnknown => StatusCode::NOT_FOUND,
+            // Reported against the manifest being pushed rather than the missing blob.
+            RegistryError::ManifestBlobUnknown(_)
+            | RegistryError::BlobUploadInvalid(_)
+            | RegistryError::DigestInvalid(_)
+            | RegistryError::ManifestInvalid
+            | RegistryError::ManifestUnverified
+            | RegistryError::RepositoryNameInvalid(_)
+            | RegistryError::SizeInvalid
+            | RegistryError::TagInvalid(_)
+            | RegistryError::ReferenceInvalid(_)
+            | RegistryError::ParametersInvalid(_) => StatusCode::BAD_REQUEST,
+            RegistryError::ManifestTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            RegistryError::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            RegistryError::Unauthorised => StatusCode::UNAUTHORIZED,
+            RegistryError::Denied => StatusCode::FORBIDDEN,
+            RegistryError::Unsupported => StatusCode::METHOD_NOT_ALLOWED,
+            RegistryError::Generic(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        let body = ErrorBody {
+            errors: vec![ErrorInfo {
+                code: self.0.as_string(),
+                message: self.0.message(),
+                detail: self.0.detail(),
+            }],
+        };
+        let mut response = (status, axum::Json(body)).into_response();
+
+        // Tells the client where to resume a chunked upload from.
+        if let RegistryError::RangeNotSatisfiable(offset) = self.0 {
+            let range = format!("0-{}", offset.saturating_sub(1));
+            if let Ok(range) = HeaderValue::try_from(range) {
+                response.headers_mut().insert(RANGE, range);
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+
+    #[tokio::test]
+    async fn renders_spec_error_body() {
+        let err = ApiError(RegistryError::DigestInvalid("Digest mismatch.".to_string()));
+
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "errors": [{
+                "code": "DIGEST_INVALID",
+                "message": "provided digest did not match uploaded content",
+                "detail": "Digest mismatch."
+            }]})
+        );
+    }
+
+    #[test]
+    fn reports_resume_offset_on_range_errors() {
+        let response = ApiError(RegistryError::RangeNotSatisfiable(10)).into_response();
+
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[RANGE], "0-9");
+    }
+}
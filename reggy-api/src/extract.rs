@@ -0,0 +1,81 @@
+use crate::error::ApiError;
+use axum::{
+    extract::{FromRequestParts, rejection::PathRejection, rejection::QueryRejection},
+    http::request::Parts,
+};
+use reggy_core::registry_error::RegistryError;
+use serde::de::DeserializeOwned;
+
+/// axum's `Query`, rejecting requests with a spec error body rather than plain text.
+pub struct Query<T>(pub T);
+
+/// axum's `Path`, rejecting requests with a spec error body rather than plain text.
+pub struct Path<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        axum::extract::Query::from_request_parts(parts, state)
+            .await
+            .map(|axum::extract::Query(query)| Query(query))
+            .map_err(|e: QueryRejection| ApiError(RegistryError::ParametersInvalid(e.body_text())))
+    }
+}
+
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        axum::extract::Path::from_request_parts(parts, state)
+            .await
+            .map(|axum::extract::Path(path)| Path(path))
+            .map_err(|e: PathRejection| ApiError(RegistryError::ParametersInvalid(e.body_text())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        http::{Request, StatusCode},
+        response::IntoResponse,
+    };
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Pagination {
+        n: Option<usize>,
+    }
+
+    fn request_parts(uri: &str) -> Parts {
+        Request::builder().uri(uri).body(()).unwrap().into_parts().0
+    }
+
+    #[tokio::test]
+    async fn rejects_with_spec_error_body() {
+        let mut parts = request_parts("/v2/a/tags/list?n=2");
+        let Ok(Query(query)) = Query::<Pagination>::from_request_parts(&mut parts, &()).await
+        else {
+            panic!("expected a query");
+        };
+        assert_eq!(query.n, Some(2));
+
+        let mut parts = request_parts("/v2/a/tags/list?n=abc");
+        let Err(err) = Query::<Pagination>::from_request_parts(&mut parts, &()).await else {
+            panic!("expected a rejection");
+        };
+        assert!(matches!(err.0, RegistryError::ParametersInvalid(_)));
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["content-type"], "application/json");
+    }
+}
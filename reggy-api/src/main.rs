@@ -1,52 +1,121 @@
+mod auth;
+mod config;
+mod error;
+mod extract;
+
+use crate::{
+    auth::authenticate,
+    config::{Args, Config, LogFormat},
+    error::ApiError,
+    extract::{Path, Query},
+};
 use axum::{
     Router,
     body::{Body, to_bytes},
-    extract::{Path, Query, Request, State},
-    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    extract::{Request, State},
+    http::{
+        HeaderMap, HeaderName, HeaderValue, StatusCode, Uri,
+        header::{CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, LOCATION},
+    },
+    middleware,
     response::IntoResponse,
-    routing::{delete, get, head, patch, post, put},
+    routing::{get, patch, post},
 };
+use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
+use futures::StreamExt;
+use http_body_util::LengthLimitError;
 use reggy_core::{
     blob::{
-        close_chunked_session, get_unqiue_upload_location, read_blob_content, remove_blob,
-        upload_chunk,
+        BlobStream, cancel_upload, close_chunked_session, expired_uploads, monolithic_upload,
+        mount_blob, read_blob_content, remove_blob, start_upload_session, upload_chunk,
+        upload_status,
     },
+    catalog::list_repositories,
     digest::Digest,
     headers::Headers,
-    manifest::{Manifest, list_tags, pull_manifest, push_manifest, remove_manifest},
+    manifest::{list_referrers, list_tags, pull_manifest, push_manifest, remove_manifest},
+    range::Range,
     reference::Reference,
     registry_error::RegistryError,
     repository_name::RepositoryName,
+    storage::Storage,
 };
-use reggy_fs::FsStore;
 use serde::Deserialize;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+
+const UPLOAD_SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Deserialize, Debug)]
 struct BlobUploadQuery {
-    pub digest: String,
+    pub digest: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct StartBlobUploadQuery {
+    pub digest: Option<String>,
+    pub mount: Option<String>,
+    pub from: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PaginationQuery {
+    pub n: Option<usize>,
+    pub last: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReferrersQuery {
+    #[serde(rename = "artifactType")]
+    pub artifact_type: Option<String>,
 }
 
 #[derive(Clone)]
 struct AppState {
     hostname: String,
     port: u16,
-    store: FsStore,
+    minimum_chunk_length: Option<usize>,
+    upload_session_ttl: Duration,
+    referrers_tag_schema: bool,
+    max_manifest_bytes: usize,
+    store: Arc<dyn Storage>,
 }
 
 #[tokio::main]
 async fn main() {
-    let fs = FsStore {
-        root_dir: "/home/adrian/code/reggy/registry".to_string(),
+    let config = match Config::load(Args::parse()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Invalid configuration: {}", err);
+            std::process::exit(1);
+        }
     };
+
+    // The filter was checked when the config was validated.
+    let filter = config.log_filter().unwrap();
+    match config.logging.format {
+        LogFormat::Text => tracing_subscriber::fmt().with_env_filter(filter).init(),
+        LogFormat::Json => tracing_subscriber::fmt()
+            .json()
+            .with_env_filter(filter)
+            .init(),
+    }
+
     let state = std::sync::Arc::new(AppState {
-        hostname: "localhost".to_string(),
-        port: 3000,
-        store: fs,
+        hostname: config.hostname().to_string(),
+        port: config.server.port,
+        minimum_chunk_length: config.limits.minimum_chunk_length,
+        upload_session_ttl: config.upload_session_ttl(),
+        referrers_tag_schema: config.referrers_tag_schema,
+        max_manifest_bytes: config.limits.max_manifest_bytes,
+        store: config.storage.open().await,
     });
 
-    let app = Router::new()
+    tokio::spawn(expire_upload_sessions(state.clone()));
+
+    let mut registry = Router::new()
         .route("/v2", get(async || StatusCode::OK))
+        .route("/v2/", get(async || StatusCode::OK))
         .route(
             "/v2/{name}/blobs/{digest}",
             get(get_blob).head(head_blobs).delete(blob_delete),
@@ -63,18 +132,42 @@ async fn main() {
             "/v2/{name}/blobs/uploads/{reference}",
             patch(blob_upload_patch)
                 .put(finalise_blob_upload)
-                .post(blob_upload)
-                .get(download_blob),
+                .get(get_upload_status)
+                .delete(cancel_blob_upload),
         )
-        .route("/v2/{name}/tags/list", get(get_tags)) // ?n={integer}&last={tagname}
-        .route("/v2/{name}/referrers/{digest}", get(get_referrers)) //?artifactType={artifactType}"
+        .route("/v2/_catalog", get(get_catalog))
+        .route("/v2/{name}/tags/list", get(get_tags))
+        .route("/v2/{name}/referrers/{digest}", get(get_referrers))
+        .method_not_allowed_fallback(async || ApiError(RegistryError::Unsupported))
         .with_state(state.clone());
+    if let Some(auth) = config.auth {
+        registry = registry.layer(middleware::from_fn_with_state(Arc::new(auth), authenticate));
+    }
 
-    let listener = tokio::net::TcpListener::bind(format!("{}:{}", state.hostname, state.port))
-        .await
-        .unwrap();
+    // Routing happens in `registry`, after repository names have been encoded.
+    let app = Router::new()
+        .fallback_service(registry)
+        .layer(middleware::map_request(encode_repository_name));
+
+    let address = format!("{}:{}", config.server.host, config.server.port);
+    let serve = async || {
+        let listener = tokio::net::TcpListener::bind(&address).await?;
+        tracing::info!("Listening on {}", address);
+        match &config.tls {
+            Some(tls) => {
+                let tls = RustlsConfig::from_pem_file(&tls.certificate, &tls.key).await?;
+                axum_server::from_tcp_rustls(listener.into_std()?, tls)?
+                    .serve(app.into_make_service())
+                    .await
+            }
+            None => axum::serve(listener, app).await,
+        }
+    };
 
-    axum::serve(listener, app).await.unwrap();
+    if let Err(err) = serve().await {
+        tracing::error!("Failed to serve on {}: {}", address, err);
+        std::process::exit(1);
+    }
 }
 
 // Blobs
@@ -85,17 +178,11 @@ async fn get_blob(
     let blob = async || {
         let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
         let digest = Digest::new(&digest)?;
-        let (blob, headers) = read_blob_content(&name, &digest, &state.store).await?;
-        return Ok::<_, RegistryError>((StatusCode::OK, create_headers(headers)?, blob));
+        let (blob, headers) = read_blob_content(&name, &digest, state.store.as_ref()).await?;
+        Ok::<_, RegistryError>((StatusCode::OK, create_headers(headers)?, stream_body(blob)))
     };
 
-    match blob().await {
-        Ok(result) => Ok(result),
-        Err(RegistryError::BlobUnknown) => {
-            Err((StatusCode::NOT_FOUND, "blob not found".to_string()))
-        }
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
-    }
+    blob().await.map_err(ApiError)
 }
 
 async fn head_blobs(
@@ -105,17 +192,11 @@ async fn head_blobs(
     let exists = async || {
         let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
         let digest = Digest::new(&digest)?;
-        let (_, headers) = read_blob_content(&name, &digest, &state.store).await?;
-        return Ok::<_, RegistryError>((StatusCode::OK, create_headers(headers)?));
+        let (_, headers) = read_blob_content(&name, &digest, state.store.as_ref()).await?;
+        Ok::<_, RegistryError>((StatusCode::OK, create_headers(headers)?))
     };
 
-    match exists().await {
-        Ok(result) => Ok(result),
-        Err(RegistryError::BlobUnknown) => {
-            Err((StatusCode::NOT_FOUND, "blob not found".to_string()))
-        }
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
-    }
+    exists().await.map_err(ApiError)
 }
 
 async fn blob_delete(
@@ -125,39 +206,60 @@ async fn blob_delete(
     let delete = async || {
         let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
         let digest = Digest::new(&digest)?;
-        remove_blob(&name, &digest, &state.store).await?;
-        return Ok::<_, RegistryError>(StatusCode::ACCEPTED);
+        remove_blob(&name, &digest, state.store.as_ref()).await?;
+        Ok::<_, RegistryError>(StatusCode::ACCEPTED)
     };
 
-    match delete().await {
-        Ok(result) => Ok(result),
-        Err(RegistryError::BlobUnknown) => {
-            Err((StatusCode::NOT_FOUND, "blob not found".to_string()))
-        }
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
-    }
+    delete().await.map_err(ApiError)
 }
 
 async fn start_blob_upload_session(
     path: Path<String>,
     state: State<Arc<AppState>>,
+    Query(query): Query<StartBlobUploadQuery>,
+    req: Request<Body>,
 ) -> impl IntoResponse {
-    let get_upload_headers = || {
+    let get_upload_headers = async || {
         let name = RepositoryName::new(&path.0, &state.hostname, Some(state.port))?;
-        let internal_headers = get_unqiue_upload_location(&name, true);
+
+        // A digest alongside the body means the whole blob is pushed in this one request.
+        if let Some(digest) = &query.digest {
+            let digest = Digest::new(digest)?;
+            let blob_length = content_length(req.headers())?;
+            let content = body_stream(req.into_body());
+            let internal_headers =
+                monolithic_upload(&name, digest, blob_length, content, state.store.as_ref())
+                    .await?;
+            let headers = create_headers(internal_headers)?;
+            return Ok::<_, RegistryError>((StatusCode::CREATED, headers));
+        }
+
+        // Mounting falls back to a regular upload session when the blob cannot be
+        // linked, including when `from` is not a valid repository name.
+        if let (Some(digest), Some(from)) = (&query.mount, &query.from) {
+            let digest = Digest::new(digest)?;
+            if let Ok(from) = RepositoryName::new(from, &state.hostname, Some(state.port)) {
+                let (mounted, internal_headers) =
+                    mount_blob(&name, &digest, &from, state.store.as_ref()).await?;
+                if mounted {
+                    let headers = create_headers(internal_headers)?;
+                    return Ok::<_, RegistryError>((StatusCode::CREATED, headers));
+                }
+            }
+        }
+
+        let (_, internal_headers) = start_upload_session(
+            &name,
+            true,
+            state.minimum_chunk_length,
+            state.store.as_ref(),
+        )
+        .await?;
         let headers = create_headers(internal_headers)?;
         Ok::<_, RegistryError>((StatusCode::ACCEPTED, headers))
     };
 
-    match get_upload_headers() {
-        Ok(result) => Ok(result),
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
-    }
-}
-
-async fn blob_upload() {
-    println!("blob_upload");
-    todo!()
+    get_upload_headers().await.map_err(ApiError)
 }
 
 async fn blob_upload_patch(
@@ -165,21 +267,43 @@ async fn blob_upload_patch(
     path: Path<(String, String)>,
     req: Request<Body>,
 ) -> impl IntoResponse {
+    let location = format!("/v2/{}/blobs/uploads/{}", path.0.0, path.0.1);
     let headers = async || {
         let name = RepositoryName::new(&path.0.0, &state.hostname, Some(state.port))?;
-        let chunk = to_bytes(req.into_body(), usize::MAX)
-            .await
-            .map_err(|e| RegistryError::Generic(e.to_string()))?
-            .to_vec();
-        let internal_headers = upload_chunk(&name, path.0.1, chunk, &state.store).await?;
+        let content_range = req
+            .headers()
+            .get(CONTENT_RANGE)
+            .map(|v| {
+                v.to_str()
+                    .map_err(|_| RegistryError::SizeInvalid)
+                    .and_then(Range::parse)
+            })
+            .transpose()?;
+        let chunk = body_stream(req.into_body());
+        let internal_headers = upload_chunk(
+            &name,
+            path.0.1,
+            content_range,
+            state.minimum_chunk_length,
+            chunk,
+            state.store.as_ref(),
+        )
+        .await?;
         let headers = create_headers(internal_headers)?;
         Ok::<_, RegistryError>((StatusCode::ACCEPTED, headers))
     };
 
-    match headers().await {
-        Ok(result) => Ok(result),
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
-    }
+    headers().await.map_err(|e| {
+        // A rejected chunk still points the client back at its session.
+        let range_error = matches!(e, RegistryError::RangeNotSatisfiable(_));
+        let mut response = ApiError(e).into_response();
+        if let Ok(location) = HeaderValue::try_from(location)
+            && range_error
+        {
+            response.headers_mut().insert(LOCATION, location);
+        }
+        response
+    })
 }
 
 async fn finalise_blob_upload(
@@ -188,49 +312,88 @@ async fn finalise_blob_upload(
     Query(query): Query<BlobUploadQuery>,
     req: Request<Body>,
 ) -> impl IntoResponse {
-    println!("finalise_blob_upload");
     let finalise = async || {
         let name = RepositoryName::new(&path.0.0, &state.hostname, Some(state.port))?;
         let session_id = &path.0.1;
-        let reference = Reference::new(&query.digest);
-
-        if let Ok(Reference::Digest(digest)) = reference {
-            let last_chunk = to_bytes(req.into_body(), usize::MAX)
-                .await
-                .map_err(|e| RegistryError::Generic(e.to_string()))?
-                .to_vec();
-
-            let mut last = None;
-            if last_chunk.len() > 0 {
-                last = Some(last_chunk);
-            }
-
-            let internal_headers =
-                close_chunked_session(&name, digest, session_id.to_string(), last, &state.store)
-                    .await?;
+        let reference = query.digest.as_deref().map(Reference::new);
+
+        if let Some(Ok(Reference::Digest(digest))) = reference {
+            let last = Some(body_stream(req.into_body()));
+            let internal_headers = close_chunked_session(
+                &name,
+                digest,
+                session_id.to_string(),
+                last,
+                state.store.as_ref(),
+            )
+            .await?;
             let headers = create_headers(internal_headers)?;
             return Ok::<_, RegistryError>((StatusCode::CREATED, headers));
         };
 
-        return Err(RegistryError::Generic(
+        Err(RegistryError::DigestInvalid(
             "Reference must be a digest upon final upload.".to_string(),
-        ));
+        ))
     };
 
-    match finalise().await {
-        Ok(result) => Ok(result),
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
-    }
+    finalise().await.map_err(ApiError)
 }
 
-async fn mount_blob() {
-    println!("mount_blob");
-    todo!()
+async fn get_upload_status(
+    state: State<Arc<AppState>>,
+    Path((name, session_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let status = async || {
+        let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
+        let internal_headers = upload_status(&name, session_id, state.store.as_ref()).await?;
+        let headers = create_headers(internal_headers)?;
+        Ok::<_, RegistryError>((StatusCode::NO_CONTENT, headers))
+    };
+
+    status().await.map_err(ApiError)
+}
+
+async fn cancel_blob_upload(
+    state: State<Arc<AppState>>,
+    Path((name, session_id)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let cancel = async || {
+        let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
+        cancel_upload(&name, session_id, state.store.as_ref()).await?;
+        Ok::<_, RegistryError>(StatusCode::NO_CONTENT)
+    };
+
+    cancel().await.map_err(ApiError)
 }
 
-async fn download_blob() {
-    println!("download_blob");
-    todo!()
+async fn expire_upload_sessions(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(UPLOAD_SESSION_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let sessions = match expired_uploads(state.upload_session_ttl, state.store.as_ref()).await {
+            Ok(sessions) => sessions,
+            Err(err) => {
+                tracing::error!("Failed to expire upload sessions: {}", err);
+                continue;
+            }
+        };
+
+        // One session failing to expire must not keep the rest around.
+        for session in sessions {
+            let expire = async || {
+                let name = RepositoryName::new(&session.name, &state.hostname, Some(state.port))?;
+                cancel_upload(&name, session.id.clone(), state.store.as_ref()).await
+            };
+            if let Err(err) = expire().await {
+                tracing::warn!(
+                    "Failed to expire upload session {} of {}: {}",
+                    session.id,
+                    session.name,
+                    err
+                );
+            }
+        }
+    }
 }
 
 // Manifest
@@ -241,19 +404,12 @@ async fn get_manifests(
     let manifest = async || {
         let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
         let reference = Reference::new(&reference)?;
-        let (m, internal_headers) = pull_manifest(name, reference, &state.store).await?;
+        let (body, internal_headers) = pull_manifest(name, reference, state.store.as_ref()).await?;
         let headers = create_headers(internal_headers)?;
-        let body = serde_json::to_vec(&m).map_err(|e| RegistryError::Generic(e.to_string()))?;
         Ok::<_, RegistryError>((headers, body))
     };
 
-    match manifest().await {
-        Ok(result) => Ok(result),
-        Err(RegistryError::ManifestUnknown) => {
-            Err((StatusCode::NOT_FOUND, "Manifest not found".to_string()))
-        }
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
-    }
+    manifest().await.map_err(ApiError)
 }
 
 async fn head_manifests(
@@ -263,17 +419,11 @@ async fn head_manifests(
     let exists = async || {
         let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
         let reference = Reference::new(&reference)?;
-        let (_, internal_headers) = pull_manifest(name, reference, &state.store).await?;
-        Ok::<_, RegistryError>(create_headers(internal_headers)?)
+        let (_, internal_headers) = pull_manifest(name, reference, state.store.as_ref()).await?;
+        create_headers(internal_headers)
     };
 
-    match exists().await {
-        Ok(headers) => Ok(headers),
-        Err(RegistryError::ManifestUnknown) => {
-            Err((StatusCode::NOT_FOUND, "No manifest found.".to_string()))
-        }
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
-    }
+    exists().await.map_err(ApiError)
 }
 
 async fn put_manifest(
@@ -284,20 +434,33 @@ async fn put_manifest(
     let put = async || {
         let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
         let reference = Reference::new(&reference)?;
-        let data = to_bytes(req.into_body(), usize::MAX)
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let data = to_bytes(req.into_body(), state.max_manifest_bytes)
             .await
-            .map_err(|e| RegistryError::Generic(e.to_string()))?
+            .map_err(|e| match e.into_inner() {
+                e if e.is::<LengthLimitError>() => {
+                    RegistryError::ManifestTooLarge(state.max_manifest_bytes)
+                }
+                e => RegistryError::Generic(e.to_string()),
+            })?
             .to_vec();
-        let manifest: Manifest =
-            serde_json::from_slice(&data).map_err(|e| RegistryError::Generic(e.to_string()))?;
-        let headers = push_manifest(&name, &reference, manifest, &state.store).await?;
-        Ok::<_, RegistryError>(create_headers(headers)?)
+        let headers = push_manifest(
+            &name,
+            &reference,
+            data,
+            content_type.as_deref(),
+            state.referrers_tag_schema,
+            state.store.as_ref(),
+        )
+        .await?;
+        Ok::<_, RegistryError>((StatusCode::CREATED, create_headers(headers)?))
     };
 
-    match put().await {
-        Ok(headers) => Ok((StatusCode::CREATED, headers)),
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
-    }
+    put().await.map_err(ApiError)
 }
 
 async fn delete_manifest(
@@ -307,37 +470,135 @@ async fn delete_manifest(
     let delete = async || {
         let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
         let reference = Reference::new(&reference)?;
-        Ok::<_, RegistryError>(remove_manifest(&name, &reference, &state.store).await?)
+        remove_manifest(
+            &name,
+            &reference,
+            state.referrers_tag_schema,
+            state.store.as_ref(),
+        )
+        .await?;
+        Ok::<_, RegistryError>(StatusCode::ACCEPTED)
     };
 
-    match delete().await {
-        Ok(()) => Ok(StatusCode::ACCEPTED),
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
-    }
+    delete().await.map_err(ApiError)
 }
 
 // Tags
-// TODO: query params
-async fn get_tags(state: State<Arc<AppState>>, Path(name): Path<String>) -> impl IntoResponse {
+async fn get_tags(
+    state: State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(query): Query<PaginationQuery>,
+) -> impl IntoResponse {
     let tags = async || {
         let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
-        let tags = list_tags(&name, &state.store)
-            .await?
-            .iter()
-            .map(|t| t.raw())
-            .collect::<Vec<_>>();
+        let (tags, mut headers) =
+            list_tags(&name, query.n, query.last.as_deref(), state.store.as_ref()).await?;
+        headers.insert_content_type("application/json");
         let bytes = serde_json::to_vec(&tags).map_err(|e| RegistryError::Generic(e.to_string()))?;
-        Ok::<_, RegistryError>(bytes)
+        Ok::<_, RegistryError>((StatusCode::OK, create_headers(headers)?, bytes))
     };
 
-    match tags().await {
-        Ok(tags) => Ok(tags),
-        Err(err) => Err((StatusCode::INTERNAL_SERVER_ERROR, err.as_string())),
+    tags().await.map_err(ApiError)
+}
+
+// Catalog
+async fn get_catalog(
+    state: State<Arc<AppState>>,
+    Query(query): Query<PaginationQuery>,
+) -> impl IntoResponse {
+    let catalog = async || {
+        let (catalog, mut headers) =
+            list_repositories(query.n, query.last.as_deref(), state.store.as_ref()).await?;
+        headers.insert_content_type("application/json");
+        let bytes =
+            serde_json::to_vec(&catalog).map_err(|e| RegistryError::Generic(e.to_string()))?;
+        Ok::<_, RegistryError>((StatusCode::OK, create_headers(headers)?, bytes))
+    };
+
+    catalog().await.map_err(ApiError)
+}
+
+// Referrers
+async fn get_referrers(
+    state: State<Arc<AppState>>,
+    Path((name, digest)): Path<(String, String)>,
+    Query(query): Query<ReferrersQuery>,
+) -> impl IntoResponse {
+    let referrers = async || {
+        let name = RepositoryName::new(&name, &state.hostname, Some(state.port))?;
+        let digest = Digest::new(&digest)?;
+        let (index, headers) = list_referrers(
+            &name,
+            &digest,
+            query.artifact_type.as_deref(),
+            state.store.as_ref(),
+        )
+        .await?;
+        let bytes =
+            serde_json::to_vec(&index).map_err(|e| RegistryError::Generic(e.to_string()))?;
+        Ok::<_, RegistryError>((StatusCode::OK, create_headers(headers)?, bytes))
+    };
+
+    referrers().await.map_err(ApiError)
+}
+
+// Repository names may span several path segments, e.g. `team/project/image`,
+// which a `{name}` route parameter cannot match. Encoding the slashes within
+// the name keeps it to one segment, and the Path extractor decodes it again.
+async fn encode_repository_name(mut req: Request) -> Request {
+    let encoded = encoded_path(req.uri().path()).map(|path| match req.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    });
+    if let Some(uri) = encoded.and_then(|e| e.parse::<Uri>().ok()) {
+        *req.uri_mut() = uri;
+    }
+    req
+}
+
+fn encoded_path(path: &str) -> Option<String> {
+    let segments = path.strip_prefix("/v2/")?.split('/').collect::<Vec<_>>();
+    let len = segments.len();
+    let name_length = match segments.as_slice() {
+        [.., "blobs", "uploads", _] if len > 3 => len - 3,
+        [.., "blobs", _] | [.., "manifests", _] | [.., "tags", "list"] | [.., "referrers", _]
+            if len > 2 =>
+        {
+            len - 2
+        }
+        _ => return None,
+    };
+
+    // Names are joined into a single segment, so traversal is refused here
+    // rather than left to reach a backend's paths.
+    let (name, rest) = segments.split_at(name_length);
+    if name.iter().any(|s| matches!(*s, "" | "." | "..")) {
+        return None;
     }
+    Some(format!("/v2/{}/{}", name.join("%2F"), rest.join("/")))
+}
+
+fn body_stream(body: Body) -> BlobStream {
+    Box::pin(
+        body.into_data_stream()
+            .map(|r| r.map_err(|e| RegistryError::Generic(e.to_string()))),
+    )
+}
+
+fn stream_body(stream: BlobStream) -> Body {
+    Body::from_stream(stream.map(|r| r.map_err(|e| std::io::Error::other(e.to_string()))))
 }
 
-async fn get_referrers() {
-    println!("get_referrers");
+fn content_length(headers: &HeaderMap) -> Result<Option<usize>, RegistryError> {
+    headers
+        .get(CONTENT_LENGTH)
+        .map(|v| {
+            v.to_str()
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .ok_or(RegistryError::SizeInvalid)
+        })
+        .transpose()
 }
 
 fn create_headers(headers: Headers) -> Result<HeaderMap, RegistryError> {
@@ -350,3 +611,48 @@ fn create_headers(headers: Headers) -> Result<HeaderMap, RegistryError> {
 
     Ok(output)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_multi_segment_names() {
+        assert_eq!(
+            encoded_path("/v2/team/project/image/manifests/latest").as_deref(),
+            Some("/v2/team%2Fproject%2Fimage/manifests/latest")
+        );
+        assert_eq!(
+            encoded_path("/v2/team/image/blobs/uploads/").as_deref(),
+            Some("/v2/team%2Fimage/blobs/uploads/")
+        );
+        assert_eq!(
+            encoded_path("/v2/team/image/blobs/uploads/abc").as_deref(),
+            Some("/v2/team%2Fimage/blobs/uploads/abc")
+        );
+        assert_eq!(
+            encoded_path("/v2/blobs/tags/list").as_deref(),
+            Some("/v2/blobs/tags/list")
+        );
+    }
+
+    #[test]
+    fn leaves_other_paths_alone() {
+        assert_eq!(encoded_path("/v2/"), None);
+        assert_eq!(encoded_path("/v2/_catalog"), None);
+        assert_eq!(encoded_path("/v2/image/unknown/thing"), None);
+    }
+
+    #[test]
+    fn refuses_traversal_in_names() {
+        for path in [
+            "/v2/a/../../../tmp/x/blobs/uploads/",
+            "/v2/../../etc/manifests/latest",
+            "/v2/_x/../tags/list",
+            "/v2/team//image/manifests/latest",
+            "/v2/./image/blobs/sha256:abc",
+        ] {
+            assert_eq!(encoded_path(path), None);
+        }
+    }
+}
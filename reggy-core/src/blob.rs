@@ -1,14 +1,30 @@
 use crate::{
-    Response, digest::Digest, headers::Headers, registry_error::RegistryError,
+    Response,
+    digest::{Digest, Hasher},
+    headers::Headers,
+    range::Range,
+    registry_error::RegistryError,
     repository_name::RepositoryName,
+    upload_session::UploadSession,
 };
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, StreamExt, stream};
 use serde::{Deserialize, Serialize};
-use std::future::Future;
+use std::{
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+pub type BlobStream = Pin<Box<dyn Stream<Item = Result<Bytes, RegistryError>> + Send>>;
 
-#[derive(Serialize, Deserialize)]
 pub struct Blob {
     pub metadata: BlobMetadata,
-    pub content: Vec<u8>,
+    pub content: BlobStream,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -17,48 +33,87 @@ pub struct BlobMetadata {
     pub content_length: usize,
 }
 
-pub trait BlobStore {
-    fn read(
+/// Blob content is stored once per digest and shared by every repository,
+/// each of which only links the blobs it references.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn read(
         &self,
         name: &RepositoryName,
         digest: &Digest,
-    ) -> impl Future<Output = Result<Option<Blob>, RegistryError>>;
+    ) -> Result<Option<Blob>, RegistryError>;
+
+    /// Appends `content` to the upload session, returning the session's total length.
+    /// Nothing is appended if `content` fails part way.
+    async fn write_chunk(
+        &self,
+        name: &RepositoryName,
+        content: BlobStream,
+        session_id: &str,
+    ) -> Result<usize, RegistryError>;
+
+    async fn read_chunk(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<Option<BlobStream>, RegistryError>;
+
+    async fn read_session(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<Option<UploadSession>, RegistryError>;
 
-    fn write(
+    async fn write_session(
         &self,
         name: &RepositoryName,
-        blob: &Blob,
-    ) -> impl Future<Output = Result<(), RegistryError>>;
+        session: &UploadSession,
+    ) -> Result<(), RegistryError>;
 
-    fn write_chunk(
+    /// Moves the content of an upload session into the blob store under `digest`
+    /// and links it from `name`. Content already stored under `digest` is kept,
+    /// and the session's copy dropped with the session.
+    async fn commit_chunk(
         &self,
         name: &RepositoryName,
-        content: &Vec<u8>,
         session_id: &str,
-    ) -> impl Future<Output = Result<(), RegistryError>>;
+        digest: &Digest,
+    ) -> Result<(), RegistryError>;
 
-    fn read_chunk(
+    /// Discards an upload session along with any content written to it.
+    async fn remove_session(
         &self,
         name: &RepositoryName,
         session_id: &str,
-    ) -> impl Future<Output = Result<Option<Vec<u8>>, RegistryError>>;
+    ) -> Result<(), RegistryError>;
+
+    /// Lists the open upload sessions of every repository.
+    async fn list_sessions(&self) -> Result<Vec<UploadSession>, RegistryError>;
 
-    fn remove(
+    /// Makes a blob stored in `from` available in `name` by linking it,
+    /// returning `false` if `from` does not hold the blob.
+    async fn link(
         &self,
+        from: &RepositoryName,
         name: &RepositoryName,
         digest: &Digest,
-    ) -> impl Future<Output = Result<(), RegistryError>>;
+    ) -> Result<bool, RegistryError>;
+
+    /// Unlinks the blob from `name` only, as other repositories may still link
+    /// the same content.
+    async fn remove(&self, name: &RepositoryName, digest: &Digest) -> Result<(), RegistryError>;
 }
 
 pub async fn read_blob_content(
     name: &RepositoryName,
     digest: &Digest,
-    blob_store: &impl BlobStore,
-) -> Result<Response<Vec<u8>>, RegistryError> {
-    if let Some(blob) = blob_store.read(&name, &digest).await?.map(|b| b.content) {
-        let mut headers = Headers::new(1);
-        headers.insert_docker_content_digest(&digest);
-        return Ok((blob, headers));
+    blob_store: &(impl BlobStore + ?Sized),
+) -> Result<Response<BlobStream>, RegistryError> {
+    if let Some(blob) = blob_store.read(name, digest).await? {
+        let mut headers = Headers::new(2);
+        headers.insert_docker_content_digest(digest);
+        headers.insert_content_length(blob.metadata.content_length);
+        return Ok((blob.content, headers));
     }
 
     Err(RegistryError::BlobUnknown)
@@ -67,7 +122,7 @@ pub async fn read_blob_content(
 pub async fn read_metadata(
     name: RepositoryName,
     digest: Digest,
-    blob_reader: &impl BlobStore,
+    blob_reader: &(impl BlobStore + ?Sized),
 ) -> Result<Option<BlobMetadata>, RegistryError> {
     Ok(blob_reader.read(&name, &digest).await?.map(|b| b.metadata))
 }
@@ -75,7 +130,7 @@ pub async fn read_metadata(
 pub async fn blob_exists(
     name: RepositoryName,
     digest: Digest,
-    blob_store: &impl BlobStore,
+    blob_store: &(impl BlobStore + ?Sized),
 ) -> Result<Response<bool>, RegistryError> {
     let mut headers = Headers::new(2);
     if let Some(Blob { metadata, .. }) = blob_store.read(&name, &digest).await? {
@@ -84,122 +139,257 @@ pub async fn blob_exists(
         return Ok((true, headers));
     }
 
-    return Ok((false, headers));
+    Ok((false, headers))
 }
 
 pub async fn monolithic_upload(
     name: &RepositoryName,
     digest: Digest,
-    blob_length: usize,
-    blob_content: Vec<u8>,
-    blob_store: &impl BlobStore,
+    blob_length: Option<usize>,
+    blob_content: BlobStream,
+    blob_store: &(impl BlobStore + ?Sized),
 ) -> Result<Headers, RegistryError> {
-    let content = blob_content.to_vec();
-    if content.len() != blob_length {
+    // The content is staged in a session of its own so nothing is visible
+    // under `digest` until it has been verified.
+    let session = UploadSession::new(name, Some(digest.clone()));
+    blob_store.write_session(name, &session).await?;
+    let session = append_chunk(name, session, blob_content, blob_store).await?;
+    let content_length = session.offset();
+    if let Some(blob_length) = blob_length.filter(|l| *l != content_length) {
+        blob_store.remove_session(name, &session.id).await?;
         return Err(RegistryError::BlobUploadInvalid(format!(
             "Blob content length mismatch. Blob content length = {}. Provided length = {}.",
-            content.len(),
-            blob_length
+            content_length, blob_length
         )));
     }
 
-    if !digest.validate(&blob_content) {
-        return Err(RegistryError::BlobUploadInvalid(format!(
-            "Blob digest mismatch."
-        )));
+    if !digest.validate(&session.hasher) {
+        blob_store.remove_session(name, &session.id).await?;
+        return Err(digest_mismatch(&digest, &session.hasher));
     }
 
-    let blob = Blob {
-        metadata: BlobMetadata {
-            digest,
-            content_length: blob_length,
-        },
-        content: blob_content,
-    };
-
-    blob_store.write(&name, &blob).await?;
-    let mut headers = Headers::new(1);
-    headers.insert_location(format!(
-        "/v2/{}/blobs/{}",
-        name.raw(),
-        blob.metadata.digest.to_string()
-    ));
+    blob_store.commit_chunk(name, &session.id, &digest).await?;
+    let mut headers = Headers::new(2);
+    headers.insert_location(format!("/v2/{}/blobs/{}", name.raw(), digest));
+    headers.insert_docker_content_digest(&digest);
     Ok(headers)
 }
 
-pub fn get_unqiue_upload_location(name: &RepositoryName, chunked_upload: bool) -> Headers {
-    let session_id = uuid::Uuid::new_v4().to_string();
-    let mut headers = Headers::new(3);
-    headers.insert_location(format!("/v2/{}/blobs/uploads/{}", name.raw(), session_id));
-    headers.insert_docker_upload_uuid(&session_id);
+pub async fn mount_blob(
+    name: &RepositoryName,
+    digest: &Digest,
+    from: &RepositoryName,
+    blob_store: &(impl BlobStore + ?Sized),
+) -> Result<Response<bool>, RegistryError> {
+    let mut headers = Headers::new(2);
+    if blob_store.link(from, name, digest).await? {
+        headers.insert_location(format!("/v2/{}/blobs/{}", name.raw(), digest));
+        headers.insert_docker_content_digest(digest);
+        return Ok((true, headers));
+    }
+
+    Ok((false, headers))
+}
+
+pub async fn start_upload_session(
+    name: &RepositoryName,
+    chunked_upload: bool,
+    minimum_chunk_length: Option<usize>,
+    blob_store: &(impl BlobStore + ?Sized),
+) -> Result<Response<UploadSession>, RegistryError> {
+    let session = UploadSession::new(name, None);
+    blob_store.write_session(name, &session).await?;
+
+    let mut headers = Headers::new(4);
+    headers.insert_location(format!("/v2/{}/blobs/uploads/{}", name.raw(), session.id));
+    headers.insert_docker_upload_uuid(&session.id);
     if chunked_upload {
         headers.insert_content_length(0);
+        if let Some(min) = minimum_chunk_length {
+            headers.insert_minimum_chunk_length(min);
+        }
     }
-    headers
+    Ok((session, headers))
 }
 
 pub async fn upload_chunk(
     name: &RepositoryName,
     session_id: String,
-    // TODO: check start is end of current content
-    //_content_range: Range,
-    blob_content: Vec<u8>,
-    blob_store: &impl BlobStore,
+    content_range: Option<Range>,
+    minimum_chunk_length: Option<usize>,
+    blob_content: BlobStream,
+    blob_store: &(impl BlobStore + ?Sized),
 ) -> Result<Headers, RegistryError> {
-    let mut content = blob_store
-        .read_chunk(name, &session_id)
-        .await?
-        .unwrap_or(vec![]);
+    let session = read_session(name, &session_id, blob_store).await?;
 
-    content.extend_from_slice(&blob_content);
-    blob_store.write_chunk(name, &content, &session_id).await?;
-    let mut headers = Headers::new(2);
-    headers.insert_location(format!("/v2/{}/blobs/uploads/{}", name.raw(), session_id));
-    if content.len() > 0 {
-        headers.insert_range(0, content.len() - 1);
+    // Chunks must arrive in order, each starting where the last one ended.
+    // Only the final chunk may be shorter than the minimum, and whether a chunk
+    // is final is only known once another one follows it.
+    let offset = session.offset();
+    let out_of_order = content_range.as_ref().is_some_and(|r| r.start() != offset);
+    if out_of_order || session.short_chunk {
+        return Err(RegistryError::RangeNotSatisfiable(offset));
     }
-    Ok(headers)
+
+    let expected_length = content_range.map(|r| r.length());
+    let blob_content = checked_chunk(blob_content, offset, expected_length);
+    let mut session = append_chunk(name, session, blob_content, blob_store).await?;
+    if minimum_chunk_length.is_some_and(|min| session.offset() - offset < min) {
+        session.short_chunk = true;
+        blob_store.write_session(name, &session).await?;
+    }
+    Ok(upload_progress(name, &session))
+}
+
+pub async fn upload_status(
+    name: &RepositoryName,
+    session_id: String,
+    blob_store: &(impl BlobStore + ?Sized),
+) -> Result<Headers, RegistryError> {
+    let session = read_session(name, &session_id, blob_store).await?;
+    Ok(upload_progress(name, &session))
 }
 
 pub async fn close_chunked_session(
     name: &RepositoryName,
     digest: Digest,
     session_id: String,
-    blob_content: Option<Vec<u8>>,
-    blob_store: &impl BlobStore,
+    blob_content: Option<BlobStream>,
+    blob_store: &(impl BlobStore + ?Sized),
 ) -> Result<Headers, RegistryError> {
-    // Q: What happens if we try close a session but the chunks thus far are empty?
-    // Going to just unwrap to [] for now.
-    let mut content = blob_store
-        .read_chunk(name, &session_id)
-        .await?
-        .unwrap_or(vec![]);
-    if let Some(final_layer) = blob_content {
-        content.extend_from_slice(&final_layer);
+    let session = read_session(name, &session_id, blob_store).await?;
+    if let Some(expected) = session.expected_digest.as_ref().filter(|e| **e != digest) {
+        return Err(RegistryError::DigestInvalid(format!(
+            "Upload session '{}' was started for digest {}.",
+            session.id, expected
+        )));
     }
 
-    if !digest.validate(&content) {
-        // TODO: ?
-    }
+    // Q: What happens if we try close a session but the chunks thus far are empty?
+    // Appending an empty final layer means the session always exists to be committed.
+    let final_layer = blob_content.unwrap_or_else(|| Box::pin(stream::empty()));
+    let session = append_chunk(name, session, final_layer, blob_store).await?;
 
-    let final_blob = &Blob {
-        metadata: BlobMetadata {
-            digest: digest.clone(),
-            content_length: content.len(),
-        },
-        content,
-    };
+    // The final layer has already been appended, so the session cannot be
+    // resumed and is discarded rather than left for a retry.
+    if !digest.validate(&session.hasher) {
+        blob_store.remove_session(name, &session.id).await?;
+        return Err(digest_mismatch(&digest, &session.hasher));
+    }
 
-    blob_store.write(name, &final_blob).await?;
+    blob_store.commit_chunk(name, &session.id, &digest).await?;
     let mut headers = Headers::new(1);
-    headers.insert_location(format!("/v2/{}/blobs/{}", name.raw(), digest.to_string()));
+    headers.insert_location(format!("/v2/{}/blobs/{}", name.raw(), digest));
     Ok(headers)
 }
 
+pub async fn cancel_upload(
+    name: &RepositoryName,
+    session_id: String,
+    blob_store: &(impl BlobStore + ?Sized),
+) -> Result<(), RegistryError> {
+    let session = read_session(name, &session_id, blob_store).await?;
+    blob_store.remove_session(name, &session.id).await
+}
+
+pub async fn expired_uploads(
+    ttl: Duration,
+    blob_store: &(impl BlobStore + ?Sized),
+) -> Result<Vec<UploadSession>, RegistryError> {
+    let now = SystemTime::now();
+    let mut sessions = blob_store.list_sessions().await?;
+    sessions.retain(|s| {
+        now.duration_since(s.last_touched)
+            .is_ok_and(|idle| idle > ttl)
+    });
+    Ok(sessions)
+}
+
 pub async fn remove_blob(
     name: &RepositoryName,
     digest: &Digest,
-    blob_store: &impl BlobStore,
+    blob_store: &(impl BlobStore + ?Sized),
 ) -> Result<(), RegistryError> {
     blob_store.remove(name, digest).await
 }
+
+// Stores key sessions by repository and id, and the session records its own
+// repository too, so one started in another repository is treated as unknown
+// rather than resumed here.
+async fn read_session(
+    name: &RepositoryName,
+    session_id: &str,
+    blob_store: &(impl BlobStore + ?Sized),
+) -> Result<UploadSession, RegistryError> {
+    match blob_store.read_session(name, session_id).await? {
+        Some(session) if session.belongs_to(name) => Ok(session),
+        _ => Err(RegistryError::BlobUploadUnknown),
+    }
+}
+
+// Fails the chunk, so the store discards it, once its length turns out to
+// differ from the declared range.
+fn checked_chunk(content: BlobStream, offset: usize, expected_length: Option<usize>) -> BlobStream {
+    let received = Arc::new(AtomicUsize::new(0));
+    let counting = received.clone();
+    let content = content.map(move |bytes| {
+        let bytes = bytes?;
+        let length = counting.fetch_add(bytes.len(), Ordering::Relaxed) + bytes.len();
+        if expected_length.is_some_and(|expected| length > expected) {
+            return Err(RegistryError::RangeNotSatisfiable(offset));
+        }
+        Ok(bytes)
+    });
+    let end = stream::once(async move {
+        let length = received.load(Ordering::Relaxed);
+        let mismatched = expected_length.is_some_and(|expected| length != expected);
+        mismatched.then_some(Err(RegistryError::RangeNotSatisfiable(offset)))
+    })
+    .filter_map(async |error| error);
+    Box::pin(content.chain(end))
+}
+
+async fn append_chunk(
+    name: &RepositoryName,
+    mut session: UploadSession,
+    content: BlobStream,
+    blob_store: &(impl BlobStore + ?Sized),
+) -> Result<UploadSession, RegistryError> {
+    let hasher = Arc::new(Mutex::new(session.hasher));
+    let hashing = hasher.clone();
+    let content = Box::pin(content.inspect(move |bytes| {
+        if let Ok(bytes) = bytes {
+            hashing.lock().unwrap().update(bytes);
+        }
+    }));
+
+    let content_length = blob_store.write_chunk(name, content, &session.id).await?;
+    session.hasher = hasher.lock().unwrap().clone();
+    if session.offset() != content_length {
+        return Err(RegistryError::BlobUploadInvalid(format!(
+            "Upload session '{}' is out of sync with its content.",
+            session.id
+        )));
+    }
+
+    session.last_touched = SystemTime::now();
+    blob_store.write_session(name, &session).await?;
+    Ok(session)
+}
+
+fn upload_progress(name: &RepositoryName, session: &UploadSession) -> Headers {
+    let mut headers = Headers::new(3);
+    headers.insert_location(format!("/v2/{}/blobs/uploads/{}", name.raw(), session.id));
+    headers.insert_docker_upload_uuid(&session.id);
+    // An empty session reports `0-0` too, as 416 responses do.
+    headers.insert_range(0, session.offset().saturating_sub(1));
+    headers
+}
+
+fn digest_mismatch(digest: &Digest, hasher: &Hasher) -> RegistryError {
+    RegistryError::DigestInvalid(format!(
+        "Blob digest mismatch. Provided digest = {}. Content digest = {}.",
+        digest,
+        hasher.digest()
+    ))
+}
@@ -0,0 +1,25 @@
+use crate::{
+    Response, headers::Headers, manifest::ManifestStore, pagination::paginate,
+    registry_error::RegistryError,
+};
+use serde::Serialize;
+
+#[derive(Serialize, Debug)]
+pub struct Catalog {
+    pub repositories: Vec<String>,
+}
+
+pub async fn list_repositories(
+    n: Option<usize>,
+    last: Option<&str>,
+    manifest_store: &(impl ManifestStore + ?Sized),
+) -> Result<Response<Catalog>, RegistryError> {
+    let repositories = manifest_store.read_repositories().await?;
+    let (repositories, next_page) = paginate(repositories, n, last);
+
+    let mut headers = Headers::new(1);
+    if let Some(next_page) = next_page {
+        headers.insert_link(format!("</v2/_catalog?{}>; rel=\"next\"", next_page));
+    }
+    Ok((Catalog { repositories }, headers))
+}
@@ -0,0 +1,818 @@
+//! Checks every storage backend must pass. Backends run them from their own
+//! tests with [`storage_conformance_tests!`](crate::storage_conformance_tests).
+
+use crate::{
+    blob::{
+        BlobStore, BlobStream, cancel_upload, close_chunked_session, monolithic_upload, mount_blob,
+        remove_blob, start_upload_session, upload_chunk, upload_status,
+    },
+    catalog::list_repositories,
+    digest::Digest,
+    manifest::{
+        DOCKER_MANIFEST_LIST, Manifest, ManifestStore, list_referrers, list_tags, pull_manifest,
+        push_manifest, remove_manifest,
+    },
+    range::Range,
+    reference::Reference,
+    registry_error::RegistryError,
+    repository_name::RepositoryName,
+    storage::Storage,
+    tag::Tag,
+};
+use bytes::Bytes;
+use futures::stream;
+
+/// Generates a `#[tokio::test]` per conformance check. `$store` is evaluated
+/// once per test and returns a guard that must outlive the store, e.g. its
+/// temporary directory, along with the store itself. Attributes given after
+/// `$store`, such as `#[ignore]`, are applied to every test.
+#[macro_export]
+macro_rules! storage_conformance_tests {
+    (@test [$(#[$attr:meta])*] $store:expr; $test:ident) => {
+        #[tokio::test]
+        $(#[$attr])*
+        async fn $test() {
+            let (_guard, store) = $store;
+            $crate::conformance::$test(&store).await;
+        }
+    };
+    (@tests $attrs:tt $store:expr; $($test:ident),* $(,)?) => {
+        $(
+            $crate::storage_conformance_tests!(@test $attrs $store; $test);
+        )*
+    };
+    ($store:expr $(, #[$attr:meta])* $(,)?) => {
+        $crate::storage_conformance_tests!(
+            @tests [$(#[$attr])*] $store;
+            monolithic_upload_rejects_mismatched_digest,
+            chunked_upload_rejects_mismatched_digest,
+            upload_chunk_rejects_out_of_order_range,
+            upload_status_reports_progress,
+            upload_chunk_rejects_length_mismatch,
+            only_final_chunk_may_be_short,
+            failed_chunk_can_be_retried,
+            cancel_upload_removes_listed_session,
+            upload_chunk_rejects_unknown_sessions,
+            mount_blob_links_existing_blob,
+            removing_blob_only_unlinks_repository,
+            manifests_are_served_byte_for_byte,
+            manifests_keep_pushed_media_type,
+            push_manifest_rejects_unknown_references,
+            remove_manifest_by_tag_only_untags,
+            remove_manifest_by_digest_removes_its_tags,
+            referrers_are_indexed_by_subject,
+            referrers_tag_schema_tracks_referrers,
+            list_tags_paginates,
+            list_repositories_walks_nested_names,
+            nested_names_do_not_collide_with_layout,
+            references_cannot_escape_repository,
+            chunked_upload_commits_matching_digest
+        );
+    };
+}
+
+fn name() -> RepositoryName {
+    RepositoryName::new("test", "localhost", None).unwrap()
+}
+
+fn content(data: &'static [u8]) -> BlobStream {
+    Box::pin(stream::iter([Ok(Bytes::from_static(data))]))
+}
+
+async fn start(name: &RepositoryName, store: &dyn Storage) -> String {
+    let (session, _) = start_upload_session(name, true, None, store).await.unwrap();
+    session.id
+}
+
+fn image_manifest(config: &Digest, layers: &[&Digest]) -> Vec<u8> {
+    let layers = layers
+        .iter()
+        .map(|l| {
+            format!(
+                r#"{{ "mediaType": "application/vnd.oci.image.layer.v1.tar", "digest": "{}", "size": 5 }}"#,
+                l
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        r#"{{ "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+        "config": {{ "mediaType": "application/vnd.oci.image.config.v1+json", "digest": "{}", "size": 2 }},
+        "layers": [{}] }}"#,
+        config, layers
+    )
+    .into_bytes()
+}
+
+fn signature_manifest(config: &Digest, subject: &[u8]) -> Vec<u8> {
+    format!(
+        r#"{{ "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+        "artifactType": "application/vnd.example.signature",
+        "config": {{ "mediaType": "application/vnd.oci.empty.v1+json", "digest": "{}", "size": 2 }},
+        "layers": [],
+        "subject": {{ "mediaType": "application/vnd.oci.image.manifest.v1+json", "digest": "{}", "size": {} }} }}"#,
+        config,
+        digest_of(subject),
+        subject.len()
+    )
+    .into_bytes()
+}
+
+fn digest_of(data: &[u8]) -> Digest {
+    Digest::sha256(data)
+}
+
+pub async fn monolithic_upload_rejects_mismatched_digest(store: &dyn Storage) {
+    let digest = digest_of(b"expected");
+
+    let result =
+        monolithic_upload(&name(), digest.clone(), Some(6), content(b"actual"), store).await;
+
+    assert!(matches!(result, Err(RegistryError::DigestInvalid(_))));
+    assert!(
+        BlobStore::read(store, &name(), &digest)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+pub async fn chunked_upload_rejects_mismatched_digest(store: &dyn Storage) {
+    let digest = digest_of(b"expected");
+    let session_id = start(&name(), store).await;
+
+    upload_chunk(
+        &name(),
+        session_id.clone(),
+        None,
+        None,
+        content(b"act"),
+        store,
+    )
+    .await
+    .unwrap();
+    let result = close_chunked_session(
+        &name(),
+        digest.clone(),
+        session_id.clone(),
+        Some(content(b"ual")),
+        store,
+    )
+    .await;
+
+    assert!(matches!(result, Err(RegistryError::DigestInvalid(_))));
+    assert!(
+        BlobStore::read(store, &name(), &digest)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        store
+            .read_chunk(&name(), &session_id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+pub async fn upload_chunk_rejects_out_of_order_range(store: &dyn Storage) {
+    let session_id = start(&name(), store).await;
+
+    upload_chunk(
+        &name(),
+        session_id.clone(),
+        None,
+        None,
+        content(b"expe"),
+        store,
+    )
+    .await
+    .unwrap();
+    let result = upload_chunk(
+        &name(),
+        session_id.clone(),
+        Some(Range::parse("5-8").unwrap()),
+        None,
+        content(b"cted"),
+        store,
+    )
+    .await;
+
+    assert!(matches!(result, Err(RegistryError::RangeNotSatisfiable(4))));
+}
+
+pub async fn failed_chunk_can_be_retried(store: &dyn Storage) {
+    let digest = digest_of(b"expected");
+    let session_id = start(&name(), store).await;
+    let interrupted: BlobStream = Box::pin(stream::iter([
+        Ok(Bytes::from_static(b"exp")),
+        Err(RegistryError::Generic("connection reset".to_string())),
+    ]));
+
+    let result = upload_chunk(&name(), session_id.clone(), None, None, interrupted, store).await;
+    assert!(result.is_err());
+
+    upload_chunk(
+        &name(),
+        session_id.clone(),
+        Some(Range::parse("0-3").unwrap()),
+        None,
+        content(b"expe"),
+        store,
+    )
+    .await
+    .unwrap();
+    close_chunked_session(&name(), digest, session_id, Some(content(b"cted")), store)
+        .await
+        .unwrap();
+}
+
+pub async fn upload_chunk_rejects_length_mismatch(store: &dyn Storage) {
+    let session_id = start(&name(), store).await;
+
+    for chunk in [content(b"exp"), content(b"expec")] {
+        let result = upload_chunk(
+            &name(),
+            session_id.clone(),
+            Some(Range::parse("0-3").unwrap()),
+            None,
+            chunk,
+            store,
+        )
+        .await;
+        assert!(matches!(result, Err(RegistryError::RangeNotSatisfiable(0))));
+    }
+
+    upload_chunk(
+        &name(),
+        session_id.clone(),
+        Some(Range::parse("0-3").unwrap()),
+        None,
+        content(b"expe"),
+        store,
+    )
+    .await
+    .unwrap();
+    let headers = upload_status(&name(), session_id, store).await.unwrap();
+    assert!(headers.into_iter().any(|(k, v)| k == "Range" && v == "0-3"));
+}
+
+// A chunk shorter than the minimum is accepted as the final one, so only a
+// chunk following it is refused.
+pub async fn only_final_chunk_may_be_short(store: &dyn Storage) {
+    let session_id = start(&name(), store).await;
+
+    for (range, chunk) in [("0-3", content(b"expe")), ("4-5", content(b"ct"))] {
+        upload_chunk(
+            &name(),
+            session_id.clone(),
+            Some(Range::parse(range).unwrap()),
+            Some(4),
+            chunk,
+            store,
+        )
+        .await
+        .unwrap();
+    }
+
+    let result = upload_chunk(
+        &name(),
+        session_id.clone(),
+        Some(Range::parse("6-7").unwrap()),
+        Some(4),
+        content(b"ed"),
+        store,
+    )
+    .await;
+    assert!(matches!(result, Err(RegistryError::RangeNotSatisfiable(6))));
+
+    close_chunked_session(&name(), digest_of(b"expect"), session_id, None, store)
+        .await
+        .unwrap();
+}
+
+pub async fn upload_status_reports_progress(store: &dyn Storage) {
+    let result = upload_status(&name(), "unknown".to_string(), store).await;
+    assert!(matches!(result, Err(RegistryError::BlobUploadUnknown)));
+
+    let session_id = start(&name(), store).await;
+    let headers = upload_status(&name(), session_id.clone(), store)
+        .await
+        .unwrap();
+    assert!(headers.into_iter().any(|(k, v)| k == "Range" && v == "0-0"));
+
+    upload_chunk(
+        &name(),
+        session_id.clone(),
+        None,
+        None,
+        content(b"expe"),
+        store,
+    )
+    .await
+    .unwrap();
+    let headers = upload_status(&name(), session_id, store).await.unwrap();
+    assert!(headers.into_iter().any(|(k, v)| k == "Range" && v == "0-3"));
+}
+
+pub async fn cancel_upload_removes_listed_session(store: &dyn Storage) {
+    let name = RepositoryName::new("team/project", "localhost", None).unwrap();
+    let session_id = start(&name, store).await;
+
+    upload_chunk(
+        &name,
+        session_id.clone(),
+        None,
+        None,
+        content(b"expe"),
+        store,
+    )
+    .await
+    .unwrap();
+    let sessions = store.list_sessions().await.unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].name, "team/project");
+    assert_eq!(sessions[0].id, session_id);
+    assert_eq!(sessions[0].offset(), 4);
+
+    cancel_upload(&name, session_id, store).await.unwrap();
+    assert!(store.list_sessions().await.unwrap().is_empty());
+}
+
+pub async fn upload_chunk_rejects_unknown_sessions(store: &dyn Storage) {
+    let other = RepositoryName::new("other", "localhost", None).unwrap();
+    let session_id = start(&other, store).await;
+
+    let made_up = upload_chunk(
+        &name(),
+        "made-up".to_string(),
+        None,
+        None,
+        content(b"expe"),
+        store,
+    )
+    .await;
+    let cross_repository =
+        upload_chunk(&name(), session_id, None, None, content(b"expe"), store).await;
+
+    assert!(matches!(made_up, Err(RegistryError::BlobUploadUnknown)));
+    assert!(matches!(
+        cross_repository,
+        Err(RegistryError::BlobUploadUnknown)
+    ));
+}
+
+pub async fn mount_blob_links_existing_blob(store: &dyn Storage) {
+    let other = RepositoryName::new("other", "localhost", None).unwrap();
+    let digest = digest_of(b"expected");
+    monolithic_upload(&other, digest.clone(), None, content(b"expected"), store)
+        .await
+        .unwrap();
+
+    let (mounted, _) = mount_blob(&name(), &digest, &other, store).await.unwrap();
+    assert!(mounted);
+    assert!(
+        BlobStore::read(store, &name(), &digest)
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    let missing = digest_of(b"missing");
+    let (mounted, _) = mount_blob(&name(), &missing, &other, store).await.unwrap();
+    assert!(!mounted);
+}
+
+pub async fn removing_blob_only_unlinks_repository(store: &dyn Storage) {
+    let other = RepositoryName::new("other", "localhost", None).unwrap();
+    let digest = digest_of(b"expected");
+    for name in [&name(), &other] {
+        monolithic_upload(name, digest.clone(), None, content(b"expected"), store)
+            .await
+            .unwrap();
+    }
+
+    remove_blob(&other, &digest, store).await.unwrap();
+    assert!(
+        BlobStore::read(store, &other, &digest)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        BlobStore::read(store, &name(), &digest)
+            .await
+            .unwrap()
+            .is_some()
+    );
+}
+
+pub async fn manifests_are_served_byte_for_byte(store: &dyn Storage) {
+    let config = digest_of(b"{}");
+    monolithic_upload(&name(), config.clone(), None, content(b"{}"), store)
+        .await
+        .unwrap();
+    let content = image_manifest(&config, &[]);
+    let digest = digest_of(&content);
+    let tag = Reference::new("latest").unwrap();
+
+    push_manifest(&name(), &tag, content.clone(), None, false, store)
+        .await
+        .unwrap();
+
+    for reference in [tag, Reference::Digest(digest.clone())] {
+        let (pulled, headers) = pull_manifest(name(), reference, store).await.unwrap();
+        assert_eq!(pulled, content);
+        assert!(
+            headers
+                .into_iter()
+                .any(|(k, v)| k == "Docker-Content-Digest" && v == digest.to_string())
+        );
+    }
+}
+
+pub async fn manifests_keep_pushed_media_type(store: &dyn Storage) {
+    // Without a `mediaType` field the kind is only known from the Content-Type.
+    let content = br#"{ "schemaVersion": 2, "manifests": [] }"#.to_vec();
+    let tag = Reference::new("latest").unwrap();
+
+    push_manifest(
+        &name(),
+        &tag,
+        content,
+        Some(DOCKER_MANIFEST_LIST),
+        false,
+        store,
+    )
+    .await
+    .unwrap();
+
+    let (_, headers) = pull_manifest(name(), tag, store).await.unwrap();
+    assert!(
+        headers
+            .into_iter()
+            .any(|(k, v)| k == "Content-Type" && v == DOCKER_MANIFEST_LIST)
+    );
+}
+
+pub async fn push_manifest_rejects_unknown_references(store: &dyn Storage) {
+    let config = digest_of(b"{}");
+    let layer = digest_of(b"layer");
+    monolithic_upload(&name(), config.clone(), None, content(b"{}"), store)
+        .await
+        .unwrap();
+    let tag = Reference::new("latest").unwrap();
+
+    let result = push_manifest(
+        &name(),
+        &tag,
+        image_manifest(&config, &[&layer]),
+        None,
+        false,
+        store,
+    )
+    .await;
+
+    match result {
+        Err(RegistryError::ManifestBlobUnknown(detail)) => {
+            assert!(detail.contains(&layer.to_string()));
+            assert!(!detail.contains(&config.to_string()));
+        }
+        other => panic!("expected MANIFEST_BLOB_UNKNOWN, got {:?}", other.err()),
+    }
+    assert!(
+        ManifestStore::read(store, &name(), &tag)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+pub async fn remove_manifest_by_tag_only_untags(store: &dyn Storage) {
+    let config = digest_of(b"{}");
+    monolithic_upload(&name(), config.clone(), None, content(b"{}"), store)
+        .await
+        .unwrap();
+    let content = image_manifest(&config, &[]);
+    let digest = Reference::Digest(digest_of(&content));
+    for tag in ["latest", "stable"] {
+        let tag = Reference::new(tag).unwrap();
+        push_manifest(&name(), &tag, content.clone(), None, false, store)
+            .await
+            .unwrap();
+    }
+
+    remove_manifest(&name(), &Reference::new("latest").unwrap(), false, store)
+        .await
+        .unwrap();
+
+    let (tags, _) = list_tags(&name(), None, None, store).await.unwrap();
+    assert_eq!(tags.tags, ["stable"]);
+    assert!(pull_manifest(name(), digest, store).await.is_ok());
+}
+
+pub async fn remove_manifest_by_digest_removes_its_tags(store: &dyn Storage) {
+    let config = digest_of(b"{}");
+    monolithic_upload(&name(), config.clone(), None, content(b"{}"), store)
+        .await
+        .unwrap();
+    let content = image_manifest(&config, &[]);
+    let digest = Reference::Digest(digest_of(&content));
+    let tag = Reference::new("latest").unwrap();
+    push_manifest(&name(), &tag, content, None, false, store)
+        .await
+        .unwrap();
+
+    remove_manifest(&name(), &digest, false, store)
+        .await
+        .unwrap();
+
+    let (tags, _) = list_tags(&name(), None, None, store).await.unwrap();
+    assert!(tags.tags.is_empty());
+    assert!(matches!(
+        pull_manifest(name(), tag, store).await,
+        Err(RegistryError::ManifestUnknown)
+    ));
+    assert!(matches!(
+        remove_manifest(&name(), &digest, false, store).await,
+        Err(RegistryError::ManifestUnknown)
+    ));
+}
+
+pub async fn referrers_are_indexed_by_subject(store: &dyn Storage) {
+    let config = digest_of(b"{}");
+    monolithic_upload(&name(), config.clone(), None, content(b"{}"), store)
+        .await
+        .unwrap();
+    let subject = image_manifest(&config, &[]);
+    let subject_digest = digest_of(&subject);
+    push_manifest(
+        &name(),
+        &Reference::Digest(subject_digest.clone()),
+        subject.clone(),
+        None,
+        false,
+        store,
+    )
+    .await
+    .unwrap();
+
+    let signature = signature_manifest(&config, &subject);
+    let signature_digest = Reference::Digest(digest_of(&signature));
+    let headers = push_manifest(&name(), &signature_digest, signature, None, false, store)
+        .await
+        .unwrap();
+    assert!(
+        headers
+            .into_iter()
+            .any(|(k, v)| k == "OCI-Subject" && v == subject_digest.to_string())
+    );
+
+    let (index, _) = list_referrers(&name(), &subject_digest, None, store)
+        .await
+        .unwrap();
+    assert_eq!(index.manifests.len(), 1);
+    assert_eq!(
+        index.manifests[0].artifact_type.as_deref(),
+        Some("application/vnd.example.signature")
+    );
+    let (index, _) = list_referrers(
+        &name(),
+        &subject_digest,
+        Some("application/vnd.example.sbom"),
+        store,
+    )
+    .await
+    .unwrap();
+    assert!(index.manifests.is_empty());
+
+    remove_manifest(&name(), &signature_digest, false, store)
+        .await
+        .unwrap();
+    let (index, _) = list_referrers(&name(), &subject_digest, None, store)
+        .await
+        .unwrap();
+    assert!(index.manifests.is_empty());
+}
+
+pub async fn referrers_tag_schema_tracks_referrers(store: &dyn Storage) {
+    let config = digest_of(b"{}");
+    monolithic_upload(&name(), config.clone(), None, content(b"{}"), store)
+        .await
+        .unwrap();
+    let subject = image_manifest(&config, &[]);
+    let signature = signature_manifest(&config, &subject);
+    let signature_digest = digest_of(&signature);
+    let sbom = String::from_utf8(signature.clone())
+        .unwrap()
+        .replace("signature", "sbom")
+        .into_bytes();
+    let sbom_digest = digest_of(&sbom);
+    let fallback_tag = format!("sha256-{}", digest_of(&subject).hex());
+
+    push_manifest(
+        &name(),
+        &Reference::Digest(signature_digest.clone()),
+        signature,
+        None,
+        true,
+        store,
+    )
+    .await
+    .unwrap();
+
+    let (index, _) = pull_manifest(name(), Reference::new(&fallback_tag).unwrap(), store)
+        .await
+        .unwrap();
+    match Manifest::parse(&index, None).unwrap() {
+        Manifest::Index { index, .. } => {
+            assert_eq!(index.manifests.len(), 1);
+            assert_eq!(index.manifests[0].digest, signature_digest.to_string());
+        }
+        _ => panic!("expected an index"),
+    }
+
+    push_manifest(
+        &name(),
+        &Reference::Digest(sbom_digest.clone()),
+        sbom,
+        None,
+        true,
+        store,
+    )
+    .await
+    .unwrap();
+    let superseded = Reference::Digest(digest_of(&index));
+    assert!(
+        ManifestStore::read(store, &name(), &superseded)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let (index, _) = pull_manifest(name(), Reference::new(&fallback_tag).unwrap(), store)
+        .await
+        .unwrap();
+
+    for referrer in [signature_digest, sbom_digest] {
+        remove_manifest(&name(), &Reference::Digest(referrer), true, store)
+            .await
+            .unwrap();
+    }
+    let (tags, _) = list_tags(&name(), None, None, store).await.unwrap();
+    assert!(tags.tags.is_empty());
+    let last = Reference::Digest(digest_of(&index));
+    assert!(
+        ManifestStore::read(store, &name(), &last)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+pub async fn list_tags_paginates(store: &dyn Storage) {
+    let digest = digest_of(b"manifest");
+    for tag in ["c", "a", "d", "b"] {
+        store
+            .write_tag(&name(), &Tag::new(tag).unwrap(), &digest)
+            .await
+            .unwrap();
+    }
+
+    let (tags, headers) = list_tags(&name(), Some(2), Some("a"), store).await.unwrap();
+    assert_eq!(tags.tags, ["b", "c"]);
+    assert!(
+        headers
+            .into_iter()
+            .any(|(k, v)| k == "Link" && v == r#"</v2/test/tags/list?n=2&last=c>; rel="next""#)
+    );
+
+    let (tags, headers) = list_tags(&name(), Some(2), Some("c"), store).await.unwrap();
+    assert_eq!(tags.tags, ["d"]);
+    assert!(headers.into_iter().all(|(k, _)| k != "Link"));
+}
+
+pub async fn list_repositories_walks_nested_names(store: &dyn Storage) {
+    for raw in ["library/alpine", "team/app", "team/app/cache", "zeta"] {
+        let name = RepositoryName::new(raw, "localhost", None).unwrap();
+        monolithic_upload(&name, digest_of(b"{}"), None, content(b"{}"), store)
+            .await
+            .unwrap();
+    }
+    start(
+        &RepositoryName::new("pending", "localhost", None).unwrap(),
+        store,
+    )
+    .await;
+
+    let (catalog, headers) = list_repositories(Some(3), None, store).await.unwrap();
+    assert_eq!(
+        catalog.repositories,
+        ["library/alpine", "team/app", "team/app/cache"]
+    );
+    assert!(
+        headers
+            .into_iter()
+            .any(|(k, v)| k == "Link"
+                && v == r#"</v2/_catalog?n=3&last=team/app/cache>; rel="next""#)
+    );
+
+    let (catalog, _) = list_repositories(Some(3), Some("team/app/cache"), store)
+        .await
+        .unwrap();
+    assert_eq!(catalog.repositories, ["zeta"]);
+}
+
+// Nested names may reuse words a backend uses for its own layout.
+pub async fn nested_names_do_not_collide_with_layout(store: &dyn Storage) {
+    let raw_names = [
+        "team",
+        "team/blob",
+        "team/blobs",
+        "team/manifest",
+        "team/tags",
+    ];
+    let config = digest_of(b"{}");
+    let manifest = image_manifest(&config, &[]);
+    for raw in raw_names {
+        let name = RepositoryName::new(raw, "localhost", None).unwrap();
+        monolithic_upload(&name, config.clone(), None, content(b"{}"), store)
+            .await
+            .unwrap();
+        let tag = Reference::new("latest").unwrap();
+        push_manifest(&name, &tag, manifest.clone(), None, false, store)
+            .await
+            .unwrap();
+    }
+
+    let (catalog, _) = list_repositories(None, None, store).await.unwrap();
+    assert_eq!(catalog.repositories, raw_names);
+    for raw in raw_names {
+        let name = RepositoryName::new(raw, "localhost", None).unwrap();
+        let (tags, _) = list_tags(&name, None, None, store).await.unwrap();
+        assert_eq!(tags.tags, ["latest"]);
+    }
+}
+
+// Digests and tags end up in backend paths and keys, so anything that could
+// step outside a repository is refused before it reaches the store.
+pub async fn references_cannot_escape_repository(store: &dyn Storage) {
+    let digest = digest_of(b"blob");
+    monolithic_upload(&name(), digest.clone(), None, content(b"blob"), store)
+        .await
+        .unwrap();
+
+    let escaping_hex = format!("../../{}", &digest.hex()[6..]);
+    for raw in [
+        "sha256:../x".to_string(),
+        format!("sha256:{}", escaping_hex),
+        format!("sha256:{}/..", digest.hex()),
+        format!("sha256:{}", digest.hex().to_uppercase()),
+    ] {
+        assert!(Digest::new(&raw).is_err(), "{}", raw);
+    }
+    for raw in ["../x", "..", "a/../b", "latest/.."] {
+        assert!(Tag::new(raw).is_err(), "{}", raw);
+        assert!(Reference::new(raw).is_err(), "{}", raw);
+    }
+
+    assert!(
+        BlobStore::read(store, &name(), &digest)
+            .await
+            .unwrap()
+            .is_some()
+    );
+}
+
+pub async fn chunked_upload_commits_matching_digest(store: &dyn Storage) {
+    let digest = digest_of(b"expected");
+    let session_id = start(&name(), store).await;
+
+    upload_chunk(
+        &name(),
+        session_id.clone(),
+        None,
+        None,
+        content(b"expe"),
+        store,
+    )
+    .await
+    .unwrap();
+    close_chunked_session(
+        &name(),
+        digest.clone(),
+        session_id,
+        Some(content(b"cted")),
+        store,
+    )
+    .await
+    .unwrap();
+
+    let blob = BlobStore::read(store, &name(), &digest)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(blob.metadata.content_length, 8);
+}
@@ -1,19 +1,28 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use sha256::Sha256Digest;
+use sha2::{
+    compress256,
+    digest::{consts::U64, generic_array::GenericArray},
+};
+use std::{fmt, slice};
 
 use crate::registry_error::RegistryError;
 
-const HASH_ALGORITHM_REGEX: &str = "[A-Fa-f0-9_+.-]+";
-const HEX_REGEX: &str = "[A-Fa-f0-9]+";
+const HASH_ALGORITHM_REGEX: &str = "^[a-z0-9]+([+._-][a-z0-9]+)*$";
+const HEX_REGEX: &str = "^[a-f0-9]+$";
+const SHA256_HEX_LENGTH: usize = 64;
+const SHA256_BLOCK_LENGTH: usize = 64;
+const SHA256_INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
 
 lazy_static! {
     static ref hex_regex: Regex = Regex::new(HEX_REGEX).unwrap();
     static ref hash_algorithm_regex: Regex = Regex::new(HASH_ALGORITHM_REGEX).unwrap();
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Hex(String);
 
 impl Hex {
@@ -35,7 +44,7 @@ impl Hex {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum HashAlgorithm {
     SHA256,
 }
@@ -50,7 +59,7 @@ impl HashAlgorithm {
 
         if hash_algorithm_regex.is_match(input) {
             match input {
-                "sha256" | "SHA256" => Ok(HashAlgorithm::SHA256),
+                "sha256" => Ok(HashAlgorithm::SHA256),
                 _ => Err(RegistryError::DigestInvalid(format!(
                     "The hash algorithm '{}' is not currently supported.",
                     input
@@ -63,9 +72,15 @@ impl HashAlgorithm {
             )))
         }
     }
+
+    fn hex_length(&self) -> usize {
+        match self {
+            HashAlgorithm::SHA256 => SHA256_HEX_LENGTH,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Digest {
     algorithm: HashAlgorithm,
     hex: Hex,
@@ -80,29 +95,174 @@ impl Digest {
         }
 
         match input.split(":").collect::<Vec<_>>().as_slice() {
-            [algorithm, hex] => Ok(Self {
-                algorithm: HashAlgorithm::new(algorithm)?,
-                hex: Hex::new(hex)?,
-            }),
+            [algorithm, hex] => {
+                let algorithm = HashAlgorithm::new(algorithm)?;
+                let hex = Hex::new(hex)?;
+                if hex.0.len() != algorithm.hex_length() {
+                    return Err(RegistryError::DigestInvalid(format!(
+                        "A {} hex must be {} characters long.",
+                        algorithm,
+                        algorithm.hex_length()
+                    )));
+                }
+                Ok(Self { algorithm, hex })
+            }
             _ => Err(RegistryError::DigestInvalid(
                 "A digest should be in the following format 'algorithm \":\" hex'".to_string(),
             )),
         }
     }
 
+    pub fn sha256(content: &[u8]) -> Self {
+        let mut hasher = Hasher::new();
+        hasher.update(content);
+        hasher.digest()
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm.clone()
+    }
+
     pub fn hex(&self) -> String {
         self.hex.0.clone()
     }
 
-    pub fn validate(&self, content: &Vec<u8>) -> bool {
+    pub fn validate(&self, hasher: &Hasher) -> bool {
         match self.algorithm {
-            HashAlgorithm::SHA256 => content.digest() == self.hex(),
+            HashAlgorithm::SHA256 => hasher.digest().hex() == self.hex(),
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
-        match self.algorithm {
-            HashAlgorithm::SHA256 => format!("sha256:{}", self.hex()),
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HashAlgorithm::SHA256 => write!(f, "sha256"),
+        }
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.hex())
+    }
+}
+
+/// A SHA-256 hasher whose intermediate state can be serialized, so hashing an
+/// upload can be resumed across requests without re-reading what came before.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Hasher {
+    state: [u32; 8],
+    buffer: Vec<u8>,
+    length: usize,
+}
+
+impl Hasher {
+    pub fn new() -> Self {
+        Self {
+            state: SHA256_INITIAL_STATE,
+            buffer: Vec::with_capacity(SHA256_BLOCK_LENGTH),
+            length: 0,
+        }
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len();
+
+        if !self.buffer.is_empty() {
+            let take = data.len().min(SHA256_BLOCK_LENGTH - self.buffer.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() < SHA256_BLOCK_LENGTH {
+                return;
+            }
+            compress(&mut self.state, &self.buffer);
+            self.buffer.clear();
+        }
+
+        let blocks = data.chunks_exact(SHA256_BLOCK_LENGTH);
+        let remainder = blocks.remainder();
+        for block in blocks {
+            compress(&mut self.state, block);
+        }
+        self.buffer.extend_from_slice(remainder);
+    }
+
+    pub fn digest(&self) -> Digest {
+        let mut state = self.state;
+        let mut block = self.buffer.clone();
+        block.push(0x80);
+        if block.len() > SHA256_BLOCK_LENGTH - 8 {
+            block.resize(SHA256_BLOCK_LENGTH, 0);
+            compress(&mut state, &block);
+            block.clear();
+        }
+        block.resize(SHA256_BLOCK_LENGTH - 8, 0);
+        block.extend_from_slice(&((self.length as u64) * 8).to_be_bytes());
+        compress(&mut state, &block);
+
+        Digest {
+            algorithm: HashAlgorithm::SHA256,
+            hex: Hex(state.iter().map(|word| format!("{:08x}", word)).collect()),
+        }
+    }
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let block: &GenericArray<u8, U64> = GenericArray::from_slice(block);
+    compress256(state, slice::from_ref(block));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest as _, Sha256};
+
+    fn expected(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    #[test]
+    fn digests_must_be_complete_lowercase_hex() {
+        let hex = expected(b"content");
+        assert!(Digest::new(&format!("sha256:{}", hex)).is_ok());
+        assert!(Digest::new(&format!("sha256:{}", &hex[1..])).is_err());
+        assert!(Digest::new(&format!("sha256:{}0", hex)).is_err());
+        assert!(Digest::new(&format!("sha256:{}", hex.to_uppercase())).is_err());
+        assert!(Digest::new(&format!("SHA256:{}", hex)).is_err());
+    }
+
+    #[test]
+    fn hasher_matches_sha256() {
+        for length in [0, 1, 55, 56, 63, 64, 65, 127, 128, 1000] {
+            let data = (0..length).map(|i| i as u8).collect::<Vec<_>>();
+            let mut hasher = Hasher::new();
+            hasher.update(&data);
+            assert_eq!(hasher.digest().hex(), expected(&data));
+        }
+    }
+
+    #[test]
+    fn hasher_resumes_from_serialized_state() {
+        let data = (0..1000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        for split in [0, 1, 63, 64, 65, 500, 1000] {
+            let mut hasher = Hasher::new();
+            hasher.update(&data[..split]);
+            let state = serde_json::to_vec(&hasher).unwrap();
+            let mut hasher: Hasher = serde_json::from_slice(&state).unwrap();
+            hasher.update(&data[split..]);
+            assert_eq!(hasher.length(), data.len());
+            assert_eq!(hasher.digest().hex(), expected(&data));
         }
     }
 }
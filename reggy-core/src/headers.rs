@@ -40,6 +40,19 @@ impl Headers {
         self.0
             .insert("Range".to_string(), format!("{}-{}", start, end));
     }
+
+    pub fn insert_link(&mut self, link: String) {
+        self.0.insert("Link".to_string(), link);
+    }
+
+    pub fn insert_oci_subject(&mut self, digest: &Digest) {
+        self.0.insert("OCI-Subject".to_string(), digest.to_string());
+    }
+
+    pub fn insert_oci_filters_applied(&mut self, filters: &str) {
+        self.0
+            .insert("OCI-Filters-Applied".to_string(), filters.to_string());
+    }
 }
 
 impl IntoIterator for Headers {
@@ -1,13 +1,19 @@
 use crate::headers::Headers;
 
 pub mod blob;
+pub mod catalog;
+#[cfg(feature = "conformance")]
+pub mod conformance;
 pub mod digest;
 pub mod headers;
 pub mod manifest;
+mod pagination;
 pub mod range;
 pub mod reference;
 pub mod registry_error;
 pub mod repository_name;
+pub mod storage;
 pub mod tag;
+pub mod upload_session;
 
 pub type Response<T> = (T, Headers);
@@ -1,85 +1,324 @@
 use crate::{
-    Response, digest::Digest, headers::Headers, reference::Reference,
-    registry_error::RegistryError, repository_name::RepositoryName, tag::Tag,
+    Response, blob::BlobStore, digest::Digest, headers::Headers, pagination::paginate,
+    reference::Reference, registry_error::RegistryError, repository_name::RepositoryName, tag::Tag,
 };
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, future::Future};
+use std::collections::HashMap;
+
+pub const OCI_IMAGE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const OCI_IMAGE_INDEX: &str = "application/vnd.oci.image.index.v1+json";
+pub const DOCKER_MANIFEST: &str = "application/vnd.docker.distribution.manifest.v2+json";
+pub const DOCKER_MANIFEST_LIST: &str = "application/vnd.docker.distribution.manifest.list.v2+json";
+const MANIFEST_MEDIA_TYPES: [&str; 4] = [
+    OCI_IMAGE_MANIFEST,
+    OCI_IMAGE_INDEX,
+    DOCKER_MANIFEST,
+    DOCKER_MANIFEST_LIST,
+];
+
+#[derive(Debug)]
+pub enum Manifest {
+    Image {
+        media_type: String,
+        manifest: Box<ImageManifest>,
+    },
+    Index {
+        media_type: String,
+        index: Box<ImageIndex>,
+    },
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
-pub struct Manifest {
+pub struct ImageManifest {
     pub schema_version: u32,
-    pub media_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
     pub config: Descriptor,
     #[serde(default)]
     pub layers: Vec<Descriptor>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<Descriptor>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub annotations: HashMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
+pub struct ImageIndex {
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+    #[serde(default)]
+    pub manifests: Vec<Descriptor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<Descriptor>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub annotations: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct Descriptor {
     pub media_type: String,
     pub digest: String,
     pub size: Option<u64>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub urls: Vec<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub annotations: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
 }
 
-pub trait ManifestStore {
-    fn read(
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Platform {
+    pub architecture: String,
+    pub os: String,
+    #[serde(
+        rename = "os.version",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub os_version: Option<String>,
+    #[serde(rename = "os.features", default, skip_serializing_if = "Vec::is_empty")]
+    pub os_features: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestKind {
+    media_type: Option<String>,
+    manifests: Option<serde::de::IgnoredAny>,
+}
+
+impl Manifest {
+    /// Parses a manifest, picking its kind from the `mediaType` it declares, then
+    /// the Content-Type it was pushed with, then its shape when neither is given.
+    pub fn parse(content: &[u8], content_type: Option<&str>) -> Result<Self, RegistryError> {
+        // Generic content types say nothing about the manifest and are ignored.
+        let content_type = content_type.filter(|t| MANIFEST_MEDIA_TYPES.contains(t));
+        let kind: ManifestKind =
+            serde_json::from_slice(content).map_err(|_| RegistryError::ManifestInvalid)?;
+        let media_type = match (kind.media_type, content_type) {
+            (Some(declared), Some(pushed)) if declared != pushed => {
+                return Err(RegistryError::ManifestInvalid);
+            }
+            (Some(declared), _) => declared,
+            (None, Some(pushed)) => pushed.to_string(),
+            (None, None) if kind.manifests.is_some() => OCI_IMAGE_INDEX.to_string(),
+            (None, None) => OCI_IMAGE_MANIFEST.to_string(),
+        };
+
+        match media_type.as_str() {
+            OCI_IMAGE_MANIFEST | DOCKER_MANIFEST => Ok(Manifest::Image {
+                manifest: serde_json::from_slice(content)
+                    .map_err(|_| RegistryError::ManifestInvalid)?,
+                media_type,
+            }),
+            OCI_IMAGE_INDEX | DOCKER_MANIFEST_LIST => Ok(Manifest::Index {
+                index: serde_json::from_slice(content)
+                    .map_err(|_| RegistryError::ManifestInvalid)?,
+                media_type,
+            }),
+            _ => Err(RegistryError::ManifestInvalid),
+        }
+    }
+
+    pub fn media_type(&self) -> &str {
+        match self {
+            Manifest::Image { media_type, .. } => media_type,
+            Manifest::Index { media_type, .. } => media_type,
+        }
+    }
+
+    pub fn subject(&self) -> Option<&Descriptor> {
+        match self {
+            Manifest::Image { manifest, .. } => manifest.subject.as_ref(),
+            Manifest::Index { index, .. } => index.subject.as_ref(),
+        }
+    }
+
+    /// Image manifests without an explicit `artifactType` are typed by their config.
+    pub fn artifact_type(&self) -> Option<&str> {
+        match self {
+            Manifest::Image { manifest, .. } => manifest
+                .artifact_type
+                .as_deref()
+                .or(Some(&manifest.config.media_type)),
+            Manifest::Index { index, .. } => index.artifact_type.as_deref(),
+        }
+    }
+
+    pub fn annotations(&self) -> &HashMap<String, String> {
+        match self {
+            Manifest::Image { manifest, .. } => &manifest.annotations,
+            Manifest::Index { index, .. } => &index.annotations,
+        }
+    }
+
+    /// Describes this manifest as it is listed among the referrers of its subject.
+    pub fn descriptor(&self, digest: &Digest, size: usize) -> Descriptor {
+        Descriptor {
+            media_type: self.media_type().to_string(),
+            digest: digest.to_string(),
+            size: Some(size as u64),
+            urls: vec![],
+            annotations: self.annotations().clone(),
+            platform: None,
+            artifact_type: self.artifact_type().map(|t| t.to_string()),
+        }
+    }
+}
+
+/// A manifest exactly as it was pushed, along with the media type it was
+/// pushed as. Manifests stored before media types were recorded have none.
+#[derive(Clone, Debug)]
+pub struct StoredManifest {
+    pub content: Vec<u8>,
+    pub media_type: Option<String>,
+}
+
+#[async_trait]
+pub trait ManifestStore: Send + Sync {
+    /// Returns the manifest exactly as it was pushed, resolving tags to the
+    /// digest they currently point at.
+    async fn read(
         &self,
         name: &RepositoryName,
         reference: &Reference,
-    ) -> impl Future<Output = Result<Option<Manifest>, RegistryError>>;
+    ) -> Result<Option<StoredManifest>, RegistryError>;
+
+    async fn write(
+        &self,
+        name: &RepositoryName,
+        digest: &Digest,
+        content: &[u8],
+        media_type: &str,
+    ) -> Result<(), RegistryError>;
 
-    fn write(
+    async fn write_tag(
+        &self,
+        name: &RepositoryName,
+        tag: &Tag,
+        digest: &Digest,
+    ) -> Result<(), RegistryError>;
+
+    async fn read_tags(&self, name: &RepositoryName) -> Result<Vec<Tag>, RegistryError>;
+
+    /// Lists the name of every repository holding manifests, tags or blob
+    /// links. Names may nest, so a repository can sit below another one.
+    async fn read_repositories(&self) -> Result<Vec<String>, RegistryError>;
+
+    /// Removing a digest deletes the manifest along with every tag pointing
+    /// at it, removing a tag only untags the manifest.
+    async fn remove(
         &self,
         name: &RepositoryName,
         reference: &Reference,
-        manifest: &Manifest,
-    ) -> impl Future<Output = Result<(), RegistryError>>;
+    ) -> Result<(), RegistryError>;
+
+    /// Records `referrer` as a manifest whose `subject` is `subject`.
+    async fn write_referrer(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+        referrer: &Descriptor,
+    ) -> Result<(), RegistryError>;
+
+    async fn read_referrers(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+    ) -> Result<Vec<Descriptor>, RegistryError>;
 
-    fn read_tags(
+    async fn remove_referrer(
         &self,
         name: &RepositoryName,
-    ) -> impl Future<Output = Result<Vec<Tag>, RegistryError>>;
+        subject: &Digest,
+        referrer: &Digest,
+    ) -> Result<(), RegistryError>;
 }
 
 pub async fn pull_manifest(
     name: RepositoryName,
     reference: Reference,
-    manifest_store: &impl ManifestStore,
-) -> Result<Response<Manifest>, RegistryError> {
-    if let Some(manifest) = manifest_store.read(&name, &reference).await? {
-        let digest = Digest::new(&manifest.config.digest)?;
-        let mut headers = Headers::new(2);
-        headers.insert_docker_content_digest(&digest);
-        headers.insert_content_type(&manifest.media_type);
-        return Ok((manifest, headers));
+    manifest_store: &(impl ManifestStore + ?Sized),
+) -> Result<Response<Vec<u8>>, RegistryError> {
+    if let Some(StoredManifest {
+        content,
+        media_type,
+    }) = manifest_store.read(&name, &reference).await?
+    {
+        let media_type = match media_type {
+            Some(media_type) => media_type,
+            None => Manifest::parse(&content, None)?.media_type().to_string(),
+        };
+        let mut headers = Headers::new(3);
+        headers.insert_docker_content_digest(&Digest::sha256(&content));
+        headers.insert_content_type(&media_type);
+        headers.insert_content_length(content.len());
+        Ok((content, headers))
     } else {
-        return Err(RegistryError::ManifestUnknown);
+        Err(RegistryError::ManifestUnknown)
     }
 }
 
 pub async fn push_manifest(
     name: &RepositoryName,
     reference: &Reference,
-    manifest: Manifest,
-    manifest_store: &impl ManifestStore,
+    content: Vec<u8>,
+    content_type: Option<&str>,
+    referrers_tag_schema: bool,
+    store: &(impl BlobStore + ManifestStore + ?Sized),
 ) -> Result<Headers, RegistryError> {
-    let digest = Digest::new(&manifest.config.digest)?;
-    if let Reference::Tag(_) = reference {
-        manifest_store
-            .write(name, &Reference::Digest(digest.clone()), &manifest)
-            .await?;
+    // The bytes are kept as pushed, parsing only checks they are a manifest.
+    let manifest = Manifest::parse(&content, content_type)?;
+    let missing = missing_references(name, &manifest, store).await?;
+    if !missing.is_empty() {
+        return Err(RegistryError::ManifestBlobUnknown(format!(
+            "Manifest references unknown digests: {}.",
+            missing.join(", ")
+        )));
     }
-    manifest_store.write(name, reference, &manifest).await?;
 
-    let mut headers = Headers::new(2);
+    let digest = Digest::sha256(&content);
+    match reference {
+        Reference::Digest(expected) if *expected != digest => {
+            return Err(RegistryError::DigestInvalid(format!(
+                "Manifest digest mismatch. Provided digest = {}. Content digest = {}.",
+                expected, digest
+            )));
+        }
+        Reference::Digest(_) => {
+            ManifestStore::write(store, name, &digest, &content, manifest.media_type()).await?;
+        }
+        Reference::Tag(tag) => {
+            ManifestStore::write(store, name, &digest, &content, manifest.media_type()).await?;
+            store.write_tag(name, tag, &digest).await?;
+        }
+    }
+
+    let mut headers = Headers::new(3);
+    // The subject need not exist yet, referrers may be pushed before it.
+    if let Some(subject) = manifest.subject() {
+        let subject = Digest::new(&subject.digest)?;
+        let referrer = manifest.descriptor(&digest, content.len());
+        store.write_referrer(name, &subject, &referrer).await?;
+        if referrers_tag_schema {
+            write_referrers_tag(name, &subject, store).await?;
+        }
+        headers.insert_oci_subject(&subject);
+    }
     headers.insert_location(format!(
         "/v2/{}/manifests/{}",
         name.raw(),
@@ -89,19 +328,223 @@ pub async fn push_manifest(
     Ok(headers)
 }
 
+// Image manifests must reference blobs and indexes must reference manifests
+// already pushed to the repository. Foreign layers carrying `urls` are fetched
+// from elsewhere and never uploaded, so they are not checked.
+async fn missing_references(
+    name: &RepositoryName,
+    manifest: &Manifest,
+    store: &(impl BlobStore + ManifestStore + ?Sized),
+) -> Result<Vec<String>, RegistryError> {
+    let mut missing = Vec::new();
+    match manifest {
+        Manifest::Image { manifest, .. } => {
+            let descriptors = std::iter::once(&manifest.config).chain(&manifest.layers);
+            for descriptor in descriptors.filter(|d| d.urls.is_empty()) {
+                let digest = Digest::new(&descriptor.digest)?;
+                if BlobStore::read(store, name, &digest).await?.is_none() {
+                    missing.push(descriptor.digest.clone());
+                }
+            }
+        }
+        Manifest::Index { index, .. } => {
+            for descriptor in &index.manifests {
+                let reference = Reference::Digest(Digest::new(&descriptor.digest)?);
+                if ManifestStore::read(store, name, &reference)
+                    .await?
+                    .is_none()
+                {
+                    missing.push(descriptor.digest.clone());
+                }
+            }
+        }
+    }
+    Ok(missing)
+}
+
 pub async fn remove_manifest(
     name: &RepositoryName,
     reference: &Reference,
-    manifest_store: &impl ManifestStore,
+    referrers_tag_schema: bool,
+    manifest_store: &(impl ManifestStore + ?Sized),
+) -> Result<(), RegistryError> {
+    // Untagging leaves the manifest, and so its place among the referrers, in place.
+    let Reference::Digest(digest) = reference else {
+        return manifest_store.remove(name, reference).await;
+    };
+
+    let subject = match manifest_store.read(name, reference).await? {
+        Some(manifest) => Manifest::parse(&manifest.content, manifest.media_type.as_deref())?
+            .subject()
+            .cloned(),
+        None => None,
+    };
+    manifest_store.remove(name, reference).await?;
+    if let Some(subject) = subject {
+        let subject = Digest::new(&subject.digest)?;
+        manifest_store
+            .remove_referrer(name, &subject, digest)
+            .await?;
+        if referrers_tag_schema {
+            write_referrers_tag(name, &subject, manifest_store).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn list_referrers(
+    name: &RepositoryName,
+    subject: &Digest,
+    artifact_type: Option<&str>,
+    manifest_store: &(impl ManifestStore + ?Sized),
+) -> Result<Response<ImageIndex>, RegistryError> {
+    let mut manifests = manifest_store.read_referrers(name, subject).await?;
+    let mut headers = Headers::new(2);
+    headers.insert_content_type(OCI_IMAGE_INDEX);
+    if let Some(artifact_type) = artifact_type {
+        manifests.retain(|d| d.artifact_type.as_deref() == Some(artifact_type));
+        headers.insert_oci_filters_applied("artifactType");
+    }
+
+    Ok((referrers_index(manifests), headers))
+}
+
+fn referrers_index(manifests: Vec<Descriptor>) -> ImageIndex {
+    ImageIndex {
+        schema_version: 2,
+        media_type: Some(OCI_IMAGE_INDEX.to_string()),
+        artifact_type: None,
+        manifests,
+        subject: None,
+        annotations: HashMap::new(),
+    }
+}
+
+// Clients predating the referrers API look referrers up through an index
+// tagged `<alg>-<hex>` after the subject digest. The tag is dropped once the
+// subject has no referrers left. Superseded indexes are removed so they don't
+// pile up in the repository.
+async fn write_referrers_tag(
+    name: &RepositoryName,
+    subject: &Digest,
+    manifest_store: &(impl ManifestStore + ?Sized),
 ) -> Result<(), RegistryError> {
-    todo!()
+    let tag = Tag::new(&format!("{}-{}", subject.algorithm(), subject.hex()))?;
+    let previous = manifest_store
+        .read(name, &Reference::Tag(tag.clone()))
+        .await?
+        .map(|manifest| Digest::sha256(&manifest.content));
+    let manifests = manifest_store.read_referrers(name, subject).await?;
+
+    let digest = if manifests.is_empty() {
+        None
+    } else {
+        let content = serde_json::to_vec(&referrers_index(manifests))
+            .map_err(|e| RegistryError::Generic(e.to_string()))?;
+        let digest = Digest::sha256(&content);
+        manifest_store
+            .write(name, &digest, &content, OCI_IMAGE_INDEX)
+            .await?;
+        manifest_store.write_tag(name, &tag, &digest).await?;
+        Some(digest)
+    };
+
+    match previous {
+        Some(previous) if Some(&previous) != digest.as_ref() => {
+            match manifest_store
+                .remove(name, &Reference::Digest(previous))
+                .await
+            {
+                Err(RegistryError::ManifestUnknown) => Ok(()),
+                result => result,
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct TagList {
+    pub name: String,
+    pub tags: Vec<String>,
 }
 
 pub async fn list_tags(
     name: &RepositoryName,
-    manifest_store: &impl ManifestStore,
-) -> Result<Vec<Tag>, RegistryError> {
-    let mut tags = manifest_store.read_tags(name).await?;
-    tags.sort_by_key(|a| a.raw());
-    Ok(tags)
+    n: Option<usize>,
+    last: Option<&str>,
+    manifest_store: &(impl ManifestStore + ?Sized),
+) -> Result<Response<TagList>, RegistryError> {
+    let tags = manifest_store
+        .read_tags(name)
+        .await?
+        .iter()
+        .map(|t| t.raw())
+        .collect();
+    let (tags, next_page) = paginate(tags, n, last);
+
+    let mut headers = Headers::new(1);
+    if let Some(next_page) = next_page {
+        headers.insert_link(format!(
+            "</v2/{}/tags/list?{}>; rel=\"next\"",
+            name.raw(),
+            next_page
+        ));
+    }
+
+    let tags = TagList {
+        name: name.raw(),
+        tags,
+    };
+    Ok((tags, headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_image_index() {
+        let content = br#"{
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.index.v1+json",
+            "manifests": [{
+                "mediaType": "application/vnd.oci.image.manifest.v1+json",
+                "digest": "sha256:abc",
+                "size": 7,
+                "platform": { "architecture": "arm64", "os": "linux", "variant": "v8" }
+            }]
+        }"#;
+
+        let manifest = Manifest::parse(content, None).unwrap();
+        assert_eq!(manifest.media_type(), OCI_IMAGE_INDEX);
+        match manifest {
+            Manifest::Index { index, .. } => {
+                let platform = index.manifests[0].platform.as_ref().unwrap();
+                assert_eq!(platform.architecture, "arm64");
+            }
+            _ => panic!("expected an index"),
+        }
+    }
+
+    #[test]
+    fn selects_kind_from_content_type() {
+        let content = br#"{ "schemaVersion": 2, "manifests": [] }"#;
+
+        let manifest = Manifest::parse(content, Some(DOCKER_MANIFEST_LIST)).unwrap();
+        assert_eq!(manifest.media_type(), DOCKER_MANIFEST_LIST);
+        assert!(Manifest::parse(content, Some(DOCKER_MANIFEST)).is_err());
+    }
+
+    #[test]
+    fn rejects_conflicting_media_types() {
+        let content = br#"{
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+            "config": { "mediaType": "application/vnd.docker.container.image.v1+json", "digest": "sha256:abc" }
+        }"#;
+
+        assert!(Manifest::parse(content, Some(DOCKER_MANIFEST)).is_ok());
+        assert!(Manifest::parse(content, Some(OCI_IMAGE_MANIFEST)).is_err());
+    }
 }
@@ -0,0 +1,44 @@
+/// Sorts `items` lexically and returns at most `n` of them following `last`,
+/// along with the query for the next page when more items remain.
+pub(crate) fn paginate(
+    mut items: Vec<String>,
+    n: Option<usize>,
+    last: Option<&str>,
+) -> (Vec<String>, Option<String>) {
+    items.retain(|i| last.is_none_or(|last| i.as_str() > last));
+    items.sort();
+
+    let mut next_page = None;
+    if let Some(n) = n.filter(|n| items.len() > *n) {
+        items.truncate(n);
+        next_page = items.last().map(|last| format!("n={}&last={}", n, last));
+    }
+    (items, next_page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn paginates_after_last() {
+        let (page, next_page) = paginate(items(&["c", "a", "d", "b"]), Some(2), Some("a"));
+        assert_eq!(page, ["b", "c"]);
+        assert_eq!(next_page.as_deref(), Some("n=2&last=c"));
+
+        let (page, next_page) = paginate(items(&["c", "a", "d", "b"]), Some(2), Some("c"));
+        assert_eq!(page, ["d"]);
+        assert_eq!(next_page, None);
+    }
+
+    #[test]
+    fn empty_page_has_no_next_page() {
+        let (page, next_page) = paginate(items(&["a"]), Some(0), None);
+        assert!(page.is_empty());
+        assert_eq!(next_page, None);
+    }
+}
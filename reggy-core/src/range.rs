@@ -19,12 +19,15 @@ impl Range {
         if range_regex.is_match(input) {
             match input.split("-").collect::<Vec<_>>().as_slice() {
                 [start, end] => {
-                    let start =
-                        usize::from_str_radix(start, 10).map_err(|_| RegistryError::SizeInvalid)?;
-                    let end =
-                        usize::from_str_radix(end, 10).map_err(|_| RegistryError::SizeInvalid)?;
+                    let start = start
+                        .parse::<usize>()
+                        .map_err(|_| RegistryError::SizeInvalid)?;
+                    let end = end
+                        .parse::<usize>()
+                        .map_err(|_| RegistryError::SizeInvalid)?;
 
-                    if start >= end {
+                    // The length, `end - start + 1`, must fit in a usize too.
+                    if start > end || end.checked_add(1).is_none() {
                         return Err(RegistryError::SizeInvalid);
                     }
 
@@ -45,4 +48,21 @@ impl Range {
     pub fn end(&self) -> usize {
         self.end
     }
+
+    pub fn length(&self) -> usize {
+        self.end - self.start + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_lengths_that_overflow() {
+        assert_eq!(Range::parse("5-6").unwrap().length(), 2);
+        assert!(Range::parse(&format!("0-{}", usize::MAX)).is_err());
+        assert!(Range::parse(&format!("1-{}", usize::MAX)).is_err());
+        assert!(Range::parse("6-5").is_err());
+    }
 }
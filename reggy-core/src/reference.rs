@@ -24,7 +24,7 @@ impl Reference {
     pub fn into_string(&self) -> String {
         match self {
             Reference::Tag(tag) => tag.raw(),
-            Reference::Digest(digest) => digest.hex(),
+            Reference::Digest(digest) => digest.to_string(),
         }
     }
 }
@@ -1,20 +1,26 @@
+use std::fmt;
+
 #[derive(Debug)]
 pub enum RegistryError {
     BlobUnknown,
     BlobUploadInvalid(String),
     BlobUploadUnknown,
     DigestInvalid(String),
-    ManifestBlobUnknown,
+    ManifestBlobUnknown(String),
     ManifestInvalid,
+    ManifestTooLarge(usize),
     ManifestUnknown,
     ManifestUnverified,
     RepositoryNameInvalid(String),
     RepositoryNameUnknown,
     SizeInvalid,
+    RangeNotSatisfiable(usize),
     TagInvalid(String),
     Unauthorised,
     Denied,
     Unsupported,
+    // Spec'd as UNSUPPORTED, for requests whose parameters cannot be used.
+    ParametersInvalid(String),
     ReferenceInvalid(String),
     Generic(String),
 }
@@ -26,20 +32,80 @@ impl RegistryError {
             RegistryError::BlobUploadInvalid(_) => "BLOB_UPLOAD_INVALID",
             RegistryError::BlobUploadUnknown => "BLOB_UPLOAD_UNKNOWN",
             RegistryError::DigestInvalid(_) => "DIGEST_INVALID",
-            RegistryError::ManifestBlobUnknown => "MANIFEST_BLOB_UNKNOWN",
+            RegistryError::ManifestBlobUnknown(_) => "MANIFEST_BLOB_UNKNOWN",
             RegistryError::ManifestInvalid => "MANIFEST_INVALID",
+            RegistryError::ManifestTooLarge(_) => "SIZE_INVALID",
             RegistryError::ManifestUnknown => "MANIFEST_UNKNOWN",
             RegistryError::ManifestUnverified => "MANIFEST_UNVERIFIED",
             RegistryError::RepositoryNameInvalid(_) => "NAME_INVALID",
             RegistryError::RepositoryNameUnknown => "NAME_UNKNOWN",
             RegistryError::SizeInvalid => "SIZE_INVALID",
+            RegistryError::RangeNotSatisfiable(_) => "RANGE_NOT_SATISFIABLE",
             RegistryError::TagInvalid(_) => "TAG_INVALID",
             RegistryError::Unauthorised => "UNAUTHORIZED",
             RegistryError::Denied => "DENIED",
-            RegistryError::Unsupported => "UNSUPPORTED",
-            RegistryError::ReferenceInvalid(e) => e,
-            RegistryError::Generic(e) => e,
+            RegistryError::Unsupported | RegistryError::ParametersInvalid(_) => "UNSUPPORTED",
+            // A reference that is not a digest is read as a tag.
+            RegistryError::ReferenceInvalid(_) => "TAG_INVALID",
+            RegistryError::Generic(_) => "UNKNOWN",
         }
         .to_string()
     }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            RegistryError::BlobUnknown => "blob unknown to registry",
+            RegistryError::BlobUploadInvalid(_) => "blob upload invalid",
+            RegistryError::BlobUploadUnknown => "blob upload unknown to registry",
+            RegistryError::DigestInvalid(_) => "provided digest did not match uploaded content",
+            RegistryError::ManifestBlobUnknown(_) => {
+                "manifest references a manifest or blob unknown to registry"
+            }
+            RegistryError::ManifestInvalid => "manifest invalid",
+            RegistryError::ManifestTooLarge(_) => "manifest exceeds the registry's size limit",
+            RegistryError::ManifestUnknown => "manifest unknown to registry",
+            RegistryError::ManifestUnverified => "manifest failed signature verification",
+            RegistryError::RepositoryNameInvalid(_) => "invalid repository name",
+            RegistryError::RepositoryNameUnknown => "repository name not known to registry",
+            RegistryError::SizeInvalid => "provided length did not match content length",
+            RegistryError::RangeNotSatisfiable(_) => "requested range not satisfiable",
+            RegistryError::TagInvalid(_) => "manifest tag did not match URI",
+            RegistryError::Unauthorised => "authentication required",
+            RegistryError::Denied => "requested access to the resource is denied",
+            RegistryError::Unsupported | RegistryError::ParametersInvalid(_) => {
+                "the operation is unsupported"
+            }
+            RegistryError::ReferenceInvalid(_) => "invalid manifest reference",
+            RegistryError::Generic(_) => "unknown error",
+        }
+    }
+
+    pub fn detail(&self) -> Option<String> {
+        match self {
+            RegistryError::BlobUploadInvalid(detail)
+            | RegistryError::DigestInvalid(detail)
+            | RegistryError::ManifestBlobUnknown(detail)
+            | RegistryError::RepositoryNameInvalid(detail)
+            | RegistryError::TagInvalid(detail)
+            | RegistryError::ReferenceInvalid(detail)
+            | RegistryError::ParametersInvalid(detail)
+            | RegistryError::Generic(detail) => Some(detail.clone()),
+            RegistryError::ManifestTooLarge(max) => {
+                Some(format!("Manifests are limited to {} bytes.", max))
+            }
+            RegistryError::RangeNotSatisfiable(offset) => {
+                Some(format!("Uploads must resume from offset {}.", offset))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.detail() {
+            Some(detail) => write!(f, "{}: {}", self.as_string(), detail),
+            None => write!(f, "{}: {}", self.as_string(), self.message()),
+        }
+    }
 }
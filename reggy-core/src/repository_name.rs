@@ -3,12 +3,14 @@ use lazy_static::lazy_static;
 use regex::Regex;
 
 const REPO_NAME_REGEX: &str =
-    "[a-z0-9]+((\\.|_|__|-+)[a-z0-9]+)*(\\/[a-z0-9]+((\\.|_|__|-+)[a-z0-9]+)*)*";
+    "^[a-z0-9]+((\\.|_|__|-+)[a-z0-9]+)*(\\/[a-z0-9]+((\\.|_|__|-+)[a-z0-9]+)*)*$";
 
 lazy_static! {
     static ref repo_name_regex: Regex = Regex::new(REPO_NAME_REGEX).unwrap();
 }
 
+/// No component of a name starts with `_`, so backends keep a repository's
+/// own entries under `_`-prefixed names that never collide with nested names.
 #[derive(Debug)]
 pub struct RepositoryName(String);
 
@@ -19,9 +21,9 @@ impl RepositoryName {
             1 + hostname.len() + name.len() + port.map(|p| p.to_string().len() + 1).unwrap_or(0);
 
         if total_length > 255 {
-            return Err(RegistryError::RepositoryNameInvalid(format!(
-                "Repository name size exceeded. 'hostname:port/name' > 255 bytes'."
-            )));
+            return Err(RegistryError::RepositoryNameInvalid(
+                "Repository name size exceeded. 'hostname:port/name' > 255 bytes'.".to_string(),
+            ));
         }
 
         if repo_name_regex.is_match(name) {
@@ -45,12 +47,25 @@ mod tests {
 
     #[test]
     fn valid_name() {
-        let raw_input = "coolest-image-name-ever:latest";
+        let raw_input = "team/coolest-image-name-ever";
         let name = RepositoryName::new(raw_input, "localhost", Some(8080));
         assert!(name.is_ok());
         assert!(name.unwrap().raw() == raw_input);
     }
 
+    #[test]
+    fn names_must_match_entirely() {
+        for raw_input in [
+            "a/../../../tmp/x",
+            "../../etc",
+            "_x/..",
+            "a//b",
+            "image:latest",
+        ] {
+            assert!(RepositoryName::new(raw_input, "localhost", None).is_err());
+        }
+    }
+
     #[test]
     fn long_name_is_invalid() {
         let long = "awonderfulserenityhastakenpossessionofmyentiresoullikethesesweetmorningsofpringwhichIenjoywithmywholeheartImaloneandfeelthecharmofexistenceinthisspotwhichwascreatedforheblissofsoulslikemineIamsohappymydearfriendsoabsorbedintheexquisithahdhfsdhfhasdfhasdhfsadhfhasdfhasdfhasdhfasdhfshadfhasdfhasdfhasdhfasdhfmyhaahfriend";
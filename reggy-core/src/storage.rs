@@ -0,0 +1,7 @@
+use crate::{blob::BlobStore, manifest::ManifestStore};
+
+/// A backend holding both blobs and manifests, usable as a trait object so the
+/// backend can be picked at runtime.
+pub trait Storage: BlobStore + ManifestStore {}
+
+impl<T: BlobStore + ManifestStore> Storage for T {}
@@ -2,13 +2,13 @@ use crate::registry_error::RegistryError;
 use lazy_static::lazy_static;
 use regex::Regex;
 
-const TAG_REGEX: &str = "[a-zA-Z0-9_][a-zA-Z0-9._-]{0,127}";
+const TAG_REGEX: &str = "^[a-zA-Z0-9_][a-zA-Z0-9._-]{0,127}$";
 
 lazy_static! {
     static ref tag_regex: Regex = Regex::new(TAG_REGEX).unwrap();
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Tag(String);
 
 impl Tag {
@@ -0,0 +1,43 @@
+use crate::{
+    digest::{Digest, Hasher},
+    repository_name::RepositoryName,
+};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadSession {
+    pub id: String,
+    pub name: String,
+    pub created_at: SystemTime,
+    pub last_touched: SystemTime,
+    pub hasher: Hasher,
+    pub expected_digest: Option<Digest>,
+    /// Set once a chunk falls short of the minimum chunk length, which only
+    /// the final chunk may do.
+    #[serde(default)]
+    pub short_chunk: bool,
+}
+
+impl UploadSession {
+    pub fn new(name: &RepositoryName, expected_digest: Option<Digest>) -> Self {
+        let now = SystemTime::now();
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.raw(),
+            created_at: now,
+            last_touched: now,
+            hasher: Hasher::new(),
+            expected_digest,
+            short_chunk: false,
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.hasher.length()
+    }
+
+    pub fn belongs_to(&self, name: &RepositoryName) -> bool {
+        self.name == name.raw()
+    }
+}
@@ -1,124 +1,352 @@
+use async_trait::async_trait;
+use futures::StreamExt;
 use reggy_core::{
-    blob::{Blob, BlobStore},
+    blob::{Blob, BlobMetadata, BlobStore, BlobStream},
     digest::Digest,
-    manifest::{Manifest, ManifestStore},
+    manifest::{Descriptor, ManifestStore, StoredManifest},
     reference::Reference,
     registry_error::RegistryError,
     repository_name::RepositoryName,
     tag::Tag,
+    upload_session::UploadSession,
 };
-use std::{fs, path::Path};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{ErrorKind, Write},
+    path::Path,
+};
+use tokio::{fs::File, io::AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+const BLOBS_DIR: &str = "_blobs";
+const LINKS_DIR: &str = "_links";
+const MANIFESTS_DIR: &str = "_manifests";
+// Sits next to each manifest, holding the media type it was pushed as.
+const MEDIA_TYPE_EXTENSION: &str = "media-type";
+const TAGS_FILE: &str = "_tags";
+const REFERRERS_DIR: &str = "_referrers";
+const UPLOADS_DIR: &str = "_uploads";
 
 #[derive(Clone)]
 pub struct FsStore {
     pub root_dir: String,
 }
 
+#[async_trait]
 impl BlobStore for FsStore {
     async fn read(
         &self,
         name: &RepositoryName,
         digest: &Digest,
     ) -> Result<Option<Blob>, RegistryError> {
-        let raw_path = path(&self.root_dir, &blob_id(name, digest));
-        if let Some(data) = read_file(Path::new(&raw_path)).map_err(RegistryError::Generic)? {
-            return serde_json::from_slice(&data)
-                .map_err(|e| RegistryError::Generic(e.to_string()));
+        let raw_link_path = path(&self.root_dir, &blob_link_id(name, digest));
+        if !fs::exists(Path::new(&raw_link_path))
+            .map_err(|e| RegistryError::Generic(e.to_string()))?
+        {
+            return Ok(None);
         }
 
-        Ok(None)
-    }
+        let raw_path = path(&self.root_dir, &blob_id(digest));
+        if let Some((file, content_length)) = open_file(Path::new(&raw_path))
+            .await
+            .map_err(RegistryError::Generic)?
+        {
+            return Ok(Some(Blob {
+                metadata: BlobMetadata {
+                    digest: digest.clone(),
+                    content_length,
+                },
+                content: file_stream(file),
+            }));
+        }
 
-    async fn write(&self, name: &RepositoryName, blob: &Blob) -> Result<(), RegistryError> {
-        let raw_path = path(&self.root_dir, &blob_id(name, &blob.metadata.digest));
-        let data = serde_json::to_vec(blob).map_err(|e| RegistryError::Generic(e.to_string()))?;
-        write_file(Path::new(&raw_path), &data).map_err(RegistryError::Generic)
+        Ok(None)
     }
 
     async fn write_chunk(
         &self,
         name: &RepositoryName,
-        content: &Vec<u8>,
+        content: BlobStream,
         session_id: &str,
-    ) -> Result<(), RegistryError> {
+    ) -> Result<usize, RegistryError> {
         let raw_path = path(&self.root_dir, &blob_chunk_id(name, session_id));
-        write_file(Path::new(&raw_path), content).map_err(RegistryError::Generic)
+        append_file(Path::new(&raw_path), content).await
     }
 
     async fn read_chunk(
         &self,
         name: &RepositoryName,
         session_id: &str,
-    ) -> Result<Option<Vec<u8>>, RegistryError> {
+    ) -> Result<Option<BlobStream>, RegistryError> {
         let raw_path = path(&self.root_dir, &blob_chunk_id(name, session_id));
-        read_file(Path::new(&raw_path)).map_err(RegistryError::Generic)
+        Ok(open_file(Path::new(&raw_path))
+            .await
+            .map_err(RegistryError::Generic)?
+            .map(|(file, _)| file_stream(file)))
+    }
+
+    async fn read_session(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<Option<UploadSession>, RegistryError> {
+        let raw_path = path(&self.root_dir, &blob_chunk_state_id(name, session_id));
+        read_session_file(Path::new(&raw_path))
+    }
+
+    async fn write_session(
+        &self,
+        name: &RepositoryName,
+        session: &UploadSession,
+    ) -> Result<(), RegistryError> {
+        let raw_path = path(&self.root_dir, &blob_chunk_state_id(name, &session.id));
+        let data =
+            serde_json::to_vec(session).map_err(|e| RegistryError::Generic(e.to_string()))?;
+        write_file(Path::new(&raw_path), &data).map_err(RegistryError::Generic)
+    }
+
+    async fn commit_chunk(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+        digest: &Digest,
+    ) -> Result<(), RegistryError> {
+        let raw_blob_path = path(&self.root_dir, &blob_id(digest));
+        if !fs::exists(Path::new(&raw_blob_path))
+            .map_err(|e| RegistryError::Generic(e.to_string()))?
+        {
+            let raw_chunk_path = path(&self.root_dir, &blob_chunk_id(name, session_id));
+            move_file(Path::new(&raw_chunk_path), Path::new(&raw_blob_path))
+                .await
+                .map_err(RegistryError::Generic)?;
+        }
+
+        write_link(&self.root_dir, name, digest)?;
+        self.remove_session(name, session_id).await
+    }
+
+    async fn remove_session(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<(), RegistryError> {
+        let raw_path = path(&self.root_dir, &blob_chunk_session_id(name, session_id));
+        match tokio::fs::remove_dir_all(Path::new(&raw_path)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(RegistryError::Generic(e.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<UploadSession>, RegistryError> {
+        let root = Path::new(&self.root_dir);
+        let mut sessions = vec![];
+        if fs::exists(root).map_err(|e| RegistryError::Generic(e.to_string()))? {
+            find_sessions(root, &mut sessions)?;
+        }
+        Ok(sessions)
+    }
+
+    async fn link(
+        &self,
+        from: &RepositoryName,
+        name: &RepositoryName,
+        digest: &Digest,
+    ) -> Result<bool, RegistryError> {
+        let raw_from_path = path(&self.root_dir, &blob_link_id(from, digest));
+        let raw_blob_path = path(&self.root_dir, &blob_id(digest));
+        for raw_path in [raw_from_path, raw_blob_path] {
+            if !fs::exists(Path::new(&raw_path))
+                .map_err(|e| RegistryError::Generic(e.to_string()))?
+            {
+                return Ok(false);
+            }
+        }
+
+        write_link(&self.root_dir, name, digest)?;
+        Ok(true)
     }
 
     async fn remove(&self, name: &RepositoryName, digest: &Digest) -> Result<(), RegistryError> {
-        let raw_path = path(&self.root_dir, &blob_id(name, &digest));
-        std::fs::remove_file(Path::new(&raw_path))
-            .map_err(|e| RegistryError::Generic(e.to_string()))
+        let raw_path = path(&self.root_dir, &blob_link_id(name, digest));
+        match std::fs::remove_file(Path::new(&raw_path)) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(RegistryError::BlobUnknown),
+            Err(e) => Err(RegistryError::Generic(e.to_string())),
+            Ok(()) => Ok(()),
+        }
     }
 }
 
+#[async_trait]
 impl ManifestStore for FsStore {
     async fn read(
         &self,
         name: &RepositoryName,
         reference: &Reference,
-    ) -> Result<Option<Manifest>, RegistryError> {
-        let raw_path = path(&self.root_dir, &manifest_id(name, reference));
-        if let Some(data) = read_file(Path::new(&raw_path)).map_err(RegistryError::Generic)? {
-            return serde_json::from_slice(&data)
-                .map_err(|e| RegistryError::Generic(e.to_string()));
-        }
+    ) -> Result<Option<StoredManifest>, RegistryError> {
+        let digest = match reference {
+            Reference::Digest(digest) => digest.clone(),
+            Reference::Tag(tag) => match read_tag_index(&self.root_dir, name)?.get(&tag.raw()) {
+                Some(digest) => Digest::new(digest)?,
+                None => return Ok(None),
+            },
+        };
 
-        Ok(None)
+        let raw_path = path(&self.root_dir, &manifest_id(name, &digest));
+        let Some(content) = read_file(Path::new(&raw_path)).map_err(RegistryError::Generic)? else {
+            return Ok(None);
+        };
+        let raw_media_type_path = path(&self.root_dir, &media_type_id(name, &digest));
+        let media_type = read_file(Path::new(&raw_media_type_path))
+            .map_err(RegistryError::Generic)?
+            .map(|data| String::from_utf8_lossy(&data).to_string());
+        Ok(Some(StoredManifest {
+            content,
+            media_type,
+        }))
     }
 
+    // The media type is written first, so a manifest is never visible without it.
     async fn write(
+        &self,
+        name: &RepositoryName,
+        digest: &Digest,
+        content: &[u8],
+        media_type: &str,
+    ) -> Result<(), RegistryError> {
+        let raw_media_type_path = path(&self.root_dir, &media_type_id(name, digest));
+        write_file(Path::new(&raw_media_type_path), media_type.as_bytes())
+            .map_err(RegistryError::Generic)?;
+        let raw_manifest_path = path(&self.root_dir, &manifest_id(name, digest));
+        write_file(Path::new(&raw_manifest_path), content).map_err(RegistryError::Generic)
+    }
+
+    async fn write_tag(
+        &self,
+        name: &RepositoryName,
+        tag: &Tag,
+        digest: &Digest,
+    ) -> Result<(), RegistryError> {
+        let mut tags = read_tag_index(&self.root_dir, name)?;
+        tags.insert(tag.raw(), digest.to_string());
+        write_tag_index(&self.root_dir, name, &tags)
+    }
+
+    async fn read_tags(&self, name: &RepositoryName) -> Result<Vec<Tag>, RegistryError> {
+        let mut output = vec![];
+        for raw_tag in read_tag_index(&self.root_dir, name)?.keys() {
+            output.push(Tag::new(raw_tag)?);
+        }
+        Ok(output)
+    }
+
+    async fn read_repositories(&self) -> Result<Vec<String>, RegistryError> {
+        let root = Path::new(&self.root_dir);
+        let mut repositories = vec![];
+        if fs::exists(root).map_err(|e| RegistryError::Generic(e.to_string()))? {
+            find_repositories(root, root, &mut repositories)?;
+        }
+        Ok(repositories)
+    }
+
+    async fn remove(
         &self,
         name: &RepositoryName,
         reference: &Reference,
-        manifest: &Manifest,
     ) -> Result<(), RegistryError> {
-        let data =
-            serde_json::to_vec(&manifest).map_err(|e| RegistryError::Generic(e.to_string()))?;
-        let raw_manifest_path = path(&self.root_dir, &manifest_id(name, reference));
-        write_file(Path::new(&raw_manifest_path), &data).map_err(RegistryError::Generic)?;
+        let mut tags = read_tag_index(&self.root_dir, name)?;
+        match reference {
+            Reference::Digest(digest) => {
+                let raw_path = path(&self.root_dir, &manifest_id(name, digest));
+                match std::fs::remove_file(Path::new(&raw_path)) {
+                    Err(e) if e.kind() == ErrorKind::NotFound => {
+                        return Err(RegistryError::ManifestUnknown);
+                    }
+                    Err(e) => return Err(RegistryError::Generic(e.to_string())),
+                    Ok(()) => {}
+                }
+                let raw_media_type_path = path(&self.root_dir, &media_type_id(name, digest));
+                match std::fs::remove_file(Path::new(&raw_media_type_path)) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => {
+                        return Err(RegistryError::Generic(e.to_string()));
+                    }
+                    _ => {}
+                }
 
-        if let Reference::Tag(t) = reference {
-            let raw_tag_path = path(&self.root_dir, &tags_id(name));
-            let raw_tags = read_file(Path::new(&raw_tag_path))
-                .map_err(|e| RegistryError::Generic(e.to_string()))?
-                .unwrap_or_default();
-            let mut current_tags: Vec<String> =
-                serde_json::from_slice(&raw_tags).unwrap_or_default();
-            let raw_tag = t.raw();
-            if !current_tags.contains(&raw_tag) {
-                current_tags.push(raw_tag);
-                let updated = serde_json::to_vec(&current_tags)
-                    .map_err(|e| RegistryError::Generic(e.to_string()))?;
-                write_file(Path::new(&raw_tag_path), &updated)
-                    .map_err(|e| RegistryError::Generic(e.to_string()))?;
+                let digest = digest.to_string();
+                let tag_count = tags.len();
+                tags.retain(|_, d| *d != digest);
+                if tags.len() == tag_count {
+                    return Ok(());
+                }
+            }
+            Reference::Tag(tag) => {
+                if tags.remove(&tag.raw()).is_none() {
+                    return Err(RegistryError::ManifestUnknown);
+                }
             }
         }
 
-        Ok(())
+        write_tag_index(&self.root_dir, name, &tags)
     }
 
-    async fn read_tags(&self, name: &RepositoryName) -> Result<Vec<Tag>, RegistryError> {
-        let raw_tag_path = path(&self.root_dir, &tags_id(name));
-        let raw_tags = read_file(Path::new(&raw_tag_path))
-            .map_err(|e| RegistryError::Generic(e.to_string()))?
-            .unwrap_or_default();
-        let mut output = vec![];
-        let data: Vec<String> =
-            serde_json::from_slice(&raw_tags).map_err(|e| RegistryError::Generic(e.to_string()))?;
-        for raw_tag in data {
-            output.push(Tag::new(&raw_tag)?);
+    async fn write_referrer(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+        referrer: &Descriptor,
+    ) -> Result<(), RegistryError> {
+        let referrer_digest = Digest::new(&referrer.digest)?;
+        let raw_path = path(
+            &self.root_dir,
+            &referrer_id(name, subject, &referrer_digest),
+        );
+        let data =
+            serde_json::to_vec(referrer).map_err(|e| RegistryError::Generic(e.to_string()))?;
+        write_file(Path::new(&raw_path), &data).map_err(RegistryError::Generic)
+    }
+
+    async fn read_referrers(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+    ) -> Result<Vec<Descriptor>, RegistryError> {
+        let raw_path = path(&self.root_dir, &referrers_id(name, subject));
+        let entries = match fs::read_dir(Path::new(&raw_path)) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(RegistryError::Generic(e.to_string())),
+            Ok(entries) => entries,
+        };
+
+        let mut referrers = vec![];
+        for entry in entries {
+            let entry = entry.map_err(|e| RegistryError::Generic(e.to_string()))?;
+            if is_temp_file(&entry.path()) {
+                continue;
+            }
+            if let Some(data) = read_file(&entry.path()).map_err(RegistryError::Generic)? {
+                referrers.push(
+                    serde_json::from_slice::<Descriptor>(&data)
+                        .map_err(|e| RegistryError::Generic(e.to_string()))?,
+                );
+            }
+        }
+        referrers.sort_by(|a, b| a.digest.cmp(&b.digest));
+        Ok(referrers)
+    }
+
+    async fn remove_referrer(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+        referrer: &Digest,
+    ) -> Result<(), RegistryError> {
+        let raw_path = path(&self.root_dir, &referrer_id(name, subject, referrer));
+        match std::fs::remove_file(Path::new(&raw_path)) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(RegistryError::Generic(e.to_string())),
+            Ok(()) => Ok(()),
         }
-        Ok(output)
     }
 }
 
@@ -126,35 +354,299 @@ fn path(root_dir: &str, id: &str) -> String {
     format!("{}/{}", root_dir, id)
 }
 
-fn manifest_id(name: &RepositoryName, reference: &Reference) -> String {
-    format!("{}/manifest/{}", name.raw(), reference.into_string())
+fn manifest_id(name: &RepositoryName, digest: &Digest) -> String {
+    format!("{}/{}/{}", name.raw(), MANIFESTS_DIR, digest.hex())
+}
+
+fn media_type_id(name: &RepositoryName, digest: &Digest) -> String {
+    format!("{}.{}", manifest_id(name, digest), MEDIA_TYPE_EXTENSION)
 }
 
 fn tags_id(name: &RepositoryName) -> String {
-    format!("{}/tags", name.raw())
+    format!("{}/{}", name.raw(), TAGS_FILE)
+}
+
+fn referrers_id(name: &RepositoryName, subject: &Digest) -> String {
+    format!("{}/{}/{}", name.raw(), REFERRERS_DIR, subject.hex())
 }
 
-fn blob_id(name: &RepositoryName, digest: &Digest) -> String {
-    format!("{}/blob/{}", name.raw(), digest.hex())
+fn referrer_id(name: &RepositoryName, subject: &Digest, referrer: &Digest) -> String {
+    format!("{}/{}", referrers_id(name, subject), referrer.hex())
+}
+
+fn blob_id(digest: &Digest) -> String {
+    format!("{}/{}/{}", BLOBS_DIR, digest.algorithm(), digest.hex())
+}
+
+fn blob_link_id(name: &RepositoryName, digest: &Digest) -> String {
+    format!("{}/{}/{}", name.raw(), LINKS_DIR, digest.hex())
+}
+
+fn blob_chunk_session_id(name: &RepositoryName, session_id: &str) -> String {
+    format!("{}/{}/{}", name.raw(), UPLOADS_DIR, session_id)
 }
 
 fn blob_chunk_id(name: &RepositoryName, session_id: &str) -> String {
-    format!("{}/blob_chunk/{}", name.raw(), session_id)
+    format!("{}/data", blob_chunk_session_id(name, session_id))
+}
+
+fn blob_chunk_state_id(name: &RepositoryName, session_id: &str) -> String {
+    format!("{}/session", blob_chunk_session_id(name, session_id))
+}
+
+fn find_sessions(dir: &Path, sessions: &mut Vec<UploadSession>) -> Result<(), RegistryError> {
+    for entry in fs::read_dir(dir).map_err(|e| RegistryError::Generic(e.to_string()))? {
+        let entry = entry.map_err(|e| RegistryError::Generic(e.to_string()))?;
+        if !entry.path().is_dir() {
+            continue;
+        }
+
+        match entry.file_name().to_str() {
+            Some(UPLOADS_DIR) => {
+                for session in
+                    fs::read_dir(entry.path()).map_err(|e| RegistryError::Generic(e.to_string()))?
+                {
+                    let session = session.map_err(|e| RegistryError::Generic(e.to_string()))?;
+                    if let Some(session) = read_session_file(&session.path().join("session"))? {
+                        sessions.push(session);
+                    }
+                }
+            }
+            Some(reserved) if reserved.starts_with('_') => {}
+            _ => find_sessions(&entry.path(), sessions)?,
+        }
+    }
+
+    Ok(())
+}
+
+fn find_repositories(
+    root: &Path,
+    dir: &Path,
+    repositories: &mut Vec<String>,
+) -> Result<(), RegistryError> {
+    let mut is_repository = false;
+    for entry in fs::read_dir(dir).map_err(|e| RegistryError::Generic(e.to_string()))? {
+        let entry = entry.map_err(|e| RegistryError::Generic(e.to_string()))?;
+        match entry.file_name().to_str() {
+            Some(LINKS_DIR) | Some(MANIFESTS_DIR) | Some(TAGS_FILE) => is_repository = true,
+            Some(reserved) if reserved.starts_with('_') => {}
+            _ if entry.path().is_dir() => find_repositories(root, &entry.path(), repositories)?,
+            _ => {}
+        }
+    }
+
+    if let Some(name) = dir.strip_prefix(root).ok().filter(|_| is_repository) {
+        repositories.push(name.to_string_lossy().to_string());
+    }
+    Ok(())
+}
+
+fn write_link(root_dir: &str, name: &RepositoryName, digest: &Digest) -> Result<(), RegistryError> {
+    let raw_path = path(root_dir, &blob_link_id(name, digest));
+    write_file(Path::new(&raw_path), &digest.to_string().into_bytes())
+        .map_err(RegistryError::Generic)
+}
+
+// The tags file maps each tag in a repository to the digest of its manifest.
+fn read_tag_index(
+    root_dir: &str,
+    name: &RepositoryName,
+) -> Result<BTreeMap<String, String>, RegistryError> {
+    let raw_path = path(root_dir, &tags_id(name));
+    match read_file(Path::new(&raw_path)).map_err(RegistryError::Generic)? {
+        Some(data) => {
+            serde_json::from_slice(&data).map_err(|e| RegistryError::Generic(e.to_string()))
+        }
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+fn write_tag_index(
+    root_dir: &str,
+    name: &RepositoryName,
+    tags: &BTreeMap<String, String>,
+) -> Result<(), RegistryError> {
+    let raw_path = path(root_dir, &tags_id(name));
+    let data = serde_json::to_vec(tags).map_err(|e| RegistryError::Generic(e.to_string()))?;
+    write_file(Path::new(&raw_path), &data).map_err(RegistryError::Generic)
+}
+
+fn read_session_file(path: &Path) -> Result<Option<UploadSession>, RegistryError> {
+    if let Some(data) = read_file(path).map_err(RegistryError::Generic)? {
+        return serde_json::from_slice(&data).map_err(|e| RegistryError::Generic(e.to_string()));
+    }
+
+    Ok(None)
 }
 
 fn read_file(path: &Path) -> Result<Option<Vec<u8>>, String> {
-    match fs::exists(&path) {
-        Ok(true) => Ok(Some(fs::read(&path).map_err(|e| e.to_string())?)),
+    match fs::exists(path) {
+        Ok(true) => Ok(Some(fs::read(path).map_err(|e| e.to_string())?)),
         Ok(false) => Ok(None),
         Err(error) => Err(error.to_string()),
     }
 }
 
-fn write_file(path: &Path, data: &Vec<u8>) -> Result<(), String> {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(&parent).map_err(|e| e.to_string())?;
+// Content is written to a temporary file beside `path` and renamed into place,
+// so readers and crashes never see it partially written under its final name.
+fn write_file(path: &Path, data: &[u8]) -> Result<(), String> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = parent.join(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+    let written = fs::File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp_path, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e.to_string());
     }
 
-    fs::write(path, data).map_err(|e| e.to_string())?;
+    sync_dir(parent)
+}
+
+// Makes a rename into `dir` durable.
+fn sync_dir(dir: &Path) -> Result<(), String> {
+    #[cfg(unix)]
+    fs::File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| e.to_string())?;
     Ok(())
 }
+
+fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+async fn open_file(path: &Path) -> Result<Option<(File, usize)>, String> {
+    match File::open(path).await {
+        Ok(file) => {
+            let metadata = file.metadata().await.map_err(|e| e.to_string())?;
+            Ok(Some((file, metadata.len() as usize)))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn file_stream(file: File) -> BlobStream {
+    Box::pin(ReaderStream::new(file).map(|r| r.map_err(|e| RegistryError::Generic(e.to_string()))))
+}
+
+async fn append_file(path: &Path, content: BlobStream) -> Result<usize, RegistryError> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| RegistryError::Generic(e.to_string()))?;
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .map_err(|e| RegistryError::Generic(e.to_string()))?;
+    let length = file
+        .metadata()
+        .await
+        .map_err(|e| RegistryError::Generic(e.to_string()))?
+        .len();
+
+    // A chunk that fails part way is cut off again, so the upload can resume
+    // from where the session last left it.
+    if let Err(e) = write_stream(&mut file, content).await {
+        file.set_len(length)
+            .await
+            .map_err(|e| RegistryError::Generic(e.to_string()))?;
+        return Err(e);
+    }
+
+    let metadata = file
+        .metadata()
+        .await
+        .map_err(|e| RegistryError::Generic(e.to_string()))?;
+    Ok(metadata.len() as usize)
+}
+
+async fn write_stream(file: &mut File, mut content: BlobStream) -> Result<(), RegistryError> {
+    while let Some(bytes) = content.next().await {
+        file.write_all(&bytes?)
+            .await
+            .map_err(|e| RegistryError::Generic(e.to_string()))?;
+    }
+    file.flush()
+        .await
+        .map_err(|e| RegistryError::Generic(e.to_string()))
+}
+
+// Chunks are only flushed as they are appended, so the content is synced
+// before it appears under its final name.
+async fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    let parent = to.parent().unwrap_or(Path::new("."));
+    tokio::fs::create_dir_all(parent)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    File::open(from)
+        .await
+        .map_err(|e| e.to_string())?
+        .sync_all()
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::fs::rename(from, to)
+        .await
+        .map_err(|e| e.to_string())?;
+    sync_dir(parent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::stream;
+    use reggy_core::blob::monolithic_upload;
+
+    fn store() -> (tempfile::TempDir, FsStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsStore {
+            root_dir: dir.path().to_string_lossy().to_string(),
+        };
+        (dir, store)
+    }
+
+    reggy_core::storage_conformance_tests!(store());
+
+    #[tokio::test]
+    async fn identical_blobs_are_stored_once() {
+        let (dir, store) = store();
+        let digest = Digest::sha256(b"expected");
+        for name in ["test", "other"] {
+            let name = RepositoryName::new(name, "localhost", None).unwrap();
+            let content = Box::pin(stream::iter([Ok(Bytes::from_static(b"expected"))]));
+            monolithic_upload(&name, digest.clone(), None, content, &store)
+                .await
+                .unwrap();
+        }
+
+        let stored = fs::read_dir(dir.path().join("_blobs/sha256")).unwrap();
+        assert_eq!(stored.count(), 1);
+    }
+
+    #[test]
+    fn write_file_replaces_content_without_leaving_temp_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repository/_tags");
+        write_file(&path, b"first").unwrap();
+        write_file(&path, b"second").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        let entries = fs::read_dir(dir.path().join("repository")).unwrap();
+        assert_eq!(entries.count(), 1);
+    }
+}
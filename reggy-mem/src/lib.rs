@@ -0,0 +1,493 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use futures::{StreamExt, stream};
+use lru::LruCache;
+use reggy_core::{
+    blob::{Blob, BlobMetadata, BlobStore, BlobStream},
+    digest::Digest,
+    manifest::{Descriptor, ManifestStore, StoredManifest},
+    reference::Reference,
+    registry_error::RegistryError,
+    repository_name::RepositoryName,
+    tag::Tag,
+    upload_session::UploadSession,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+/// Keeps everything in memory, for tests and throwaway registries. Clones
+/// share the same content.
+#[derive(Clone, Default)]
+pub struct MemStore {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    blobs: Mutex<BlobCache>,
+    // Upload sessions and their content, keyed by repository and session id.
+    sessions: DashMap<(String, String), UploadSession>,
+    chunks: DashMap<(String, String), BytesMut>,
+    repositories: DashMap<String, Repository>,
+}
+
+struct BlobCache {
+    content: LruCache<String, Bytes>,
+    length: usize,
+    max_length: Option<usize>,
+}
+
+#[derive(Default)]
+struct Repository {
+    links: HashSet<String>,
+    manifests: HashMap<String, StoredManifest>,
+    tags: BTreeMap<String, String>,
+    // Referrer descriptors by subject, then by referrer digest.
+    referrers: HashMap<String, BTreeMap<String, Descriptor>>,
+}
+
+impl Default for BlobCache {
+    fn default() -> Self {
+        Self {
+            content: LruCache::unbounded(),
+            length: 0,
+            max_length: None,
+        }
+    }
+}
+
+impl MemStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caps the total size of blob content held. Once full, the least recently
+    /// used blobs are evicted and read as unknown from then on. Only committed
+    /// blobs count toward the cap, the content of uploads in progress and
+    /// manifests are held in full on top of it.
+    pub fn with_max_blob_bytes(max_blob_bytes: usize) -> Self {
+        let store = Self::default();
+        store.inner.blobs.lock().unwrap().max_length = Some(max_blob_bytes);
+        store
+    }
+
+    /// The total size of blob content currently held.
+    pub fn blob_bytes(&self) -> usize {
+        self.inner.blobs.lock().unwrap().length
+    }
+
+    fn has_link(&self, name: &RepositoryName, digest: &Digest) -> bool {
+        self.inner
+            .repositories
+            .get(&name.raw())
+            .is_some_and(|r| r.links.contains(&digest.to_string()))
+    }
+
+    fn write_link(&self, name: &RepositoryName, digest: &Digest) {
+        self.inner
+            .repositories
+            .entry(name.raw())
+            .or_default()
+            .links
+            .insert(digest.to_string());
+    }
+}
+
+impl BlobCache {
+    fn insert(&mut self, digest: String, content: Bytes) -> Result<(), RegistryError> {
+        if self.content.get(&digest).is_some() {
+            return Ok(());
+        }
+        if let Some(max_length) = self.max_length.filter(|max| content.len() > *max) {
+            return Err(RegistryError::BlobUploadInvalid(format!(
+                "Blob of {} bytes exceeds the store's capacity of {} bytes.",
+                content.len(),
+                max_length
+            )));
+        }
+
+        self.length += content.len();
+        self.content.put(digest, content);
+        while self.max_length.is_some_and(|max| self.length > max) {
+            match self.content.pop_lru() {
+                Some((_, evicted)) => self.length -= evicted.len(),
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BlobStore for MemStore {
+    async fn read(
+        &self,
+        name: &RepositoryName,
+        digest: &Digest,
+    ) -> Result<Option<Blob>, RegistryError> {
+        if !self.has_link(name, digest) {
+            return Ok(None);
+        }
+
+        let content = self
+            .inner
+            .blobs
+            .lock()
+            .unwrap()
+            .content
+            .get(&digest.to_string())
+            .cloned();
+        Ok(content.map(|content| Blob {
+            metadata: BlobMetadata {
+                digest: digest.clone(),
+                content_length: content.len(),
+            },
+            content: bytes_stream(content),
+        }))
+    }
+
+    async fn write_chunk(
+        &self,
+        name: &RepositoryName,
+        mut content: BlobStream,
+        session_id: &str,
+    ) -> Result<usize, RegistryError> {
+        // The stream is drained before touching the map so no shard lock is
+        // held across an await.
+        let mut data = BytesMut::new();
+        while let Some(bytes) = content.next().await {
+            data.extend_from_slice(&bytes?);
+        }
+
+        let mut chunk = self
+            .inner
+            .chunks
+            .entry((name.raw(), session_id.to_string()))
+            .or_default();
+        chunk.extend_from_slice(&data);
+        Ok(chunk.len())
+    }
+
+    async fn read_chunk(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<Option<BlobStream>, RegistryError> {
+        Ok(self
+            .inner
+            .chunks
+            .get(&(name.raw(), session_id.to_string()))
+            .map(|chunk| bytes_stream(Bytes::copy_from_slice(&chunk))))
+    }
+
+    async fn read_session(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<Option<UploadSession>, RegistryError> {
+        Ok(self
+            .inner
+            .sessions
+            .get(&(name.raw(), session_id.to_string()))
+            .map(|session| session.clone()))
+    }
+
+    async fn write_session(
+        &self,
+        name: &RepositoryName,
+        session: &UploadSession,
+    ) -> Result<(), RegistryError> {
+        self.inner
+            .sessions
+            .insert((name.raw(), session.id.clone()), session.clone());
+        Ok(())
+    }
+
+    async fn commit_chunk(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+        digest: &Digest,
+    ) -> Result<(), RegistryError> {
+        let content = self
+            .inner
+            .chunks
+            .remove(&(name.raw(), session_id.to_string()))
+            .map(|(_, chunk)| chunk.freeze())
+            .unwrap_or_default();
+        let inserted = self
+            .inner
+            .blobs
+            .lock()
+            .unwrap()
+            .insert(digest.to_string(), content);
+        if inserted.is_ok() {
+            self.write_link(name, digest);
+        }
+
+        self.remove_session(name, session_id).await?;
+        inserted
+    }
+
+    async fn remove_session(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<(), RegistryError> {
+        let key = (name.raw(), session_id.to_string());
+        self.inner.sessions.remove(&key);
+        self.inner.chunks.remove(&key);
+        Ok(())
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<UploadSession>, RegistryError> {
+        Ok(self
+            .inner
+            .sessions
+            .iter()
+            .map(|session| session.clone())
+            .collect())
+    }
+
+    async fn link(
+        &self,
+        from: &RepositoryName,
+        name: &RepositoryName,
+        digest: &Digest,
+    ) -> Result<bool, RegistryError> {
+        let stored = self
+            .inner
+            .blobs
+            .lock()
+            .unwrap()
+            .content
+            .contains(&digest.to_string());
+        if !stored || !self.has_link(from, digest) {
+            return Ok(false);
+        }
+
+        self.write_link(name, digest);
+        Ok(true)
+    }
+
+    async fn remove(&self, name: &RepositoryName, digest: &Digest) -> Result<(), RegistryError> {
+        let removed = self
+            .inner
+            .repositories
+            .get_mut(&name.raw())
+            .is_some_and(|mut r| r.links.remove(&digest.to_string()));
+        if !removed {
+            return Err(RegistryError::BlobUnknown);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ManifestStore for MemStore {
+    async fn read(
+        &self,
+        name: &RepositoryName,
+        reference: &Reference,
+    ) -> Result<Option<StoredManifest>, RegistryError> {
+        let Some(repository) = self.inner.repositories.get(&name.raw()) else {
+            return Ok(None);
+        };
+
+        let digest = match reference {
+            Reference::Digest(digest) => digest.to_string(),
+            Reference::Tag(tag) => match repository.tags.get(&tag.raw()) {
+                Some(digest) => digest.clone(),
+                None => return Ok(None),
+            },
+        };
+        Ok(repository.manifests.get(&digest).cloned())
+    }
+
+    async fn write(
+        &self,
+        name: &RepositoryName,
+        digest: &Digest,
+        content: &[u8],
+        media_type: &str,
+    ) -> Result<(), RegistryError> {
+        let manifest = StoredManifest {
+            content: content.to_vec(),
+            media_type: Some(media_type.to_string()),
+        };
+        self.inner
+            .repositories
+            .entry(name.raw())
+            .or_default()
+            .manifests
+            .insert(digest.to_string(), manifest);
+        Ok(())
+    }
+
+    async fn write_tag(
+        &self,
+        name: &RepositoryName,
+        tag: &Tag,
+        digest: &Digest,
+    ) -> Result<(), RegistryError> {
+        self.inner
+            .repositories
+            .entry(name.raw())
+            .or_default()
+            .tags
+            .insert(tag.raw(), digest.to_string());
+        Ok(())
+    }
+
+    async fn read_tags(&self, name: &RepositoryName) -> Result<Vec<Tag>, RegistryError> {
+        let mut output = vec![];
+        if let Some(repository) = self.inner.repositories.get(&name.raw()) {
+            for raw_tag in repository.tags.keys() {
+                output.push(Tag::new(raw_tag)?);
+            }
+        }
+        Ok(output)
+    }
+
+    async fn read_repositories(&self) -> Result<Vec<String>, RegistryError> {
+        Ok(self
+            .inner
+            .repositories
+            .iter()
+            .filter(|r| !r.links.is_empty() || !r.manifests.is_empty() || !r.tags.is_empty())
+            .map(|r| r.key().clone())
+            .collect())
+    }
+
+    async fn remove(
+        &self,
+        name: &RepositoryName,
+        reference: &Reference,
+    ) -> Result<(), RegistryError> {
+        let Some(mut repository) = self.inner.repositories.get_mut(&name.raw()) else {
+            return Err(RegistryError::ManifestUnknown);
+        };
+
+        match reference {
+            Reference::Digest(digest) => {
+                let digest = digest.to_string();
+                if repository.manifests.remove(&digest).is_none() {
+                    return Err(RegistryError::ManifestUnknown);
+                }
+                repository.tags.retain(|_, d| *d != digest);
+            }
+            Reference::Tag(tag) => {
+                if repository.tags.remove(&tag.raw()).is_none() {
+                    return Err(RegistryError::ManifestUnknown);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_referrer(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+        referrer: &Descriptor,
+    ) -> Result<(), RegistryError> {
+        self.inner
+            .repositories
+            .entry(name.raw())
+            .or_default()
+            .referrers
+            .entry(subject.to_string())
+            .or_default()
+            .insert(referrer.digest.clone(), referrer.clone());
+        Ok(())
+    }
+
+    async fn read_referrers(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+    ) -> Result<Vec<Descriptor>, RegistryError> {
+        Ok(self
+            .inner
+            .repositories
+            .get(&name.raw())
+            .and_then(|r| r.referrers.get(&subject.to_string()).cloned())
+            .map(|referrers| referrers.into_values().collect())
+            .unwrap_or_default())
+    }
+
+    async fn remove_referrer(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+        referrer: &Digest,
+    ) -> Result<(), RegistryError> {
+        if let Some(mut repository) = self.inner.repositories.get_mut(&name.raw()) {
+            let subject = subject.to_string();
+            if let Some(referrers) = repository.referrers.get_mut(&subject) {
+                referrers.remove(&referrer.to_string());
+                if referrers.is_empty() {
+                    repository.referrers.remove(&subject);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn bytes_stream(content: Bytes) -> BlobStream {
+    Box::pin(stream::iter([Ok(content)]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reggy_core::blob::monolithic_upload;
+
+    reggy_core::storage_conformance_tests!(((), MemStore::new()));
+
+    async fn push(store: &MemStore, content: &'static [u8]) -> Result<Digest, RegistryError> {
+        let name = RepositoryName::new("test", "localhost", None).unwrap();
+        let digest = Digest::sha256(content);
+        let stream = Box::pin(stream::iter([Ok(Bytes::from_static(content))]));
+        monolithic_upload(&name, digest.clone(), None, stream, store).await?;
+        Ok(digest)
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_blobs() {
+        let store = MemStore::with_max_blob_bytes(10);
+        let name = RepositoryName::new("test", "localhost", None).unwrap();
+        let first = push(&store, b"first").await.unwrap();
+        let second = push(&store, b"secnd").await.unwrap();
+        assert!(
+            BlobStore::read(&store, &name, &first)
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        let third = push(&store, b"third").await.unwrap();
+        assert!(
+            BlobStore::read(&store, &name, &second)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        for digest in [first, third] {
+            assert!(
+                BlobStore::read(&store, &name, &digest)
+                    .await
+                    .unwrap()
+                    .is_some()
+            );
+        }
+        assert_eq!(store.blob_bytes(), 10);
+
+        assert!(push(&store, b"larger than the cap").await.is_err());
+        assert!(store.list_sessions().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,900 @@
+use async_trait::async_trait;
+use aws_sdk_s3::{
+    Client,
+    config::{BehaviorVersion, Region},
+    error::DisplayErrorContext,
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
+};
+use bytes::{Bytes, BytesMut};
+use futures::{StreamExt, stream};
+use reggy_core::{
+    blob::{Blob, BlobMetadata, BlobStore, BlobStream},
+    digest::Digest,
+    manifest::{Descriptor, ManifestStore, StoredManifest},
+    reference::Reference,
+    registry_error::RegistryError,
+    repository_name::RepositoryName,
+    tag::Tag,
+    upload_session::UploadSession,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Every part of a multipart upload but the last must be at least 5 MiB, so
+// chunks are buffered until a part is full. S3 allows 10,000 parts, which
+// caps a blob pushed in chunks at 80 GiB.
+const PART_LENGTH: usize = 8 * 1024 * 1024;
+// CopyObject is limited to 5 GiB, larger objects are copied part by part.
+const MAX_COPY_LENGTH: usize = 5 * 1024 * 1024 * 1024;
+const COPY_PART_LENGTH: usize = 1024 * 1024 * 1024;
+
+const BLOBS_DIR: &str = "_blobs";
+const LINKS_DIR: &str = "_links";
+const MANIFESTS_DIR: &str = "_manifests";
+const TAGS_OBJECT: &str = "_tags";
+const REFERRERS_DIR: &str = "_referrers";
+const UPLOADS_DIR: &str = "_uploads";
+
+/// Stores each repository's blobs, manifests and tags as objects under
+/// `prefix` in an S3 bucket, laid out like `FsStore`'s directories.
+#[derive(Clone)]
+pub struct S3Store {
+    client: Client,
+    bucket: String,
+    prefix: String,
+}
+
+// An upload session's multipart upload, created once its first part is full.
+#[derive(Serialize, Deserialize, Default)]
+struct Multipart {
+    upload_id: Option<String>,
+    parts: Vec<Part>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Part {
+    e_tag: String,
+    length: usize,
+}
+
+impl S3Store {
+    pub fn new(client: Client, bucket: &str, prefix: &str) -> Self {
+        Self {
+            client,
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+        }
+    }
+
+    /// Connects with credentials from the standard AWS sources, such as the
+    /// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` environment variables.
+    /// Buckets behind a custom `endpoint`, e.g. MinIO, are addressed by path.
+    pub async fn connect(
+        bucket: &str,
+        prefix: &str,
+        endpoint: Option<&str>,
+        region: Option<&str>,
+    ) -> Self {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(region) = region {
+            loader = loader.region(Region::new(region.to_string()));
+        }
+
+        let mut config = aws_sdk_s3::config::Builder::from(&loader.load().await);
+        if let Some(endpoint) = endpoint {
+            config = config.endpoint_url(endpoint).force_path_style(true);
+        }
+        Self::new(Client::from_conf(config.build()), bucket, prefix)
+    }
+
+    fn key(&self, id: &str) -> String {
+        match self.prefix.as_str() {
+            "" => id.to_string(),
+            prefix => format!("{}/{}", prefix, id),
+        }
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<(ByteStream, usize)>, RegistryError> {
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(id))
+            .send()
+            .await;
+        match result {
+            Ok(output) => {
+                let content_length = output.content_length().unwrap_or_default() as usize;
+                Ok(Some((output.body, content_length)))
+            }
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+            Err(e) => Err(s3_error(e)),
+        }
+    }
+
+    async fn read_object(&self, id: &str) -> Result<Option<Bytes>, RegistryError> {
+        match self.get(id).await? {
+            Some((body, _)) => Ok(Some(body.collect().await.map_err(s3_error)?.into_bytes())),
+            None => Ok(None),
+        }
+    }
+
+    async fn write_object(&self, id: &str, content: impl Into<Bytes>) -> Result<(), RegistryError> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.key(id))
+            .body(ByteStream::from(content.into()))
+            .send()
+            .await
+            .map_err(s3_error)?;
+        Ok(())
+    }
+
+    async fn object_length(&self, id: &str) -> Result<Option<usize>, RegistryError> {
+        let result = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(id))
+            .send()
+            .await;
+        match result {
+            Ok(output) => Ok(Some(output.content_length().unwrap_or_default() as usize)),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+            Err(e) => Err(s3_error(e)),
+        }
+    }
+
+    async fn remove_object(&self, id: &str) -> Result<(), RegistryError> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.key(id))
+            .send()
+            .await
+            .map_err(s3_error)?;
+        Ok(())
+    }
+
+    /// Lists the ids of every object below `id`.
+    async fn list_objects(&self, id: &str) -> Result<Vec<String>, RegistryError> {
+        let root = self.key("");
+        let prefix = match id {
+            "" => root.clone(),
+            id => format!("{}/", self.key(id)),
+        };
+
+        let mut ids = vec![];
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            for object in page.map_err(s3_error)?.contents() {
+                if let Some(id) = object.key().and_then(|k| k.strip_prefix(&root)) {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Lists the names of the directories and objects directly below `id`.
+    async fn list_dir(&self, id: &str) -> Result<(Vec<String>, Vec<String>), RegistryError> {
+        let prefix = match id {
+            "" => self.key(""),
+            id => format!("{}/", self.key(id)),
+        };
+
+        let (mut dirs, mut objects) = (vec![], vec![]);
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&prefix)
+            .delimiter("/")
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(s3_error)?;
+            for dir in page.common_prefixes() {
+                if let Some(name) = dir.prefix().and_then(|p| entry_name(&prefix, p)) {
+                    dirs.push(name.to_string());
+                }
+            }
+            for object in page.contents() {
+                if let Some(name) = object.key().and_then(|k| entry_name(&prefix, k)) {
+                    objects.push(name.to_string());
+                }
+            }
+        }
+        Ok((dirs, objects))
+    }
+
+    // Copies server side, so the content never passes through the registry.
+    async fn copy_object(&self, from: &str, to: &str, length: usize) -> Result<(), RegistryError> {
+        let source = format!("{}/{}", self.bucket, self.key(from));
+        if length <= MAX_COPY_LENGTH {
+            self.client
+                .copy_object()
+                .bucket(&self.bucket)
+                .copy_source(source)
+                .key(self.key(to))
+                .send()
+                .await
+                .map_err(s3_error)?;
+            return Ok(());
+        }
+
+        let upload_id = self.create_multipart(to).await?;
+        let mut parts = vec![];
+        for (index, start) in (0..length).step_by(COPY_PART_LENGTH).enumerate() {
+            let end = (start + COPY_PART_LENGTH).min(length) - 1;
+            let output = self
+                .client
+                .upload_part_copy()
+                .bucket(&self.bucket)
+                .key(self.key(to))
+                .upload_id(&upload_id)
+                .part_number(index as i32 + 1)
+                .copy_source(&source)
+                .copy_source_range(format!("bytes={}-{}", start, end))
+                .send()
+                .await
+                .map_err(s3_error)?;
+            let e_tag = output.copy_part_result().and_then(|r| r.e_tag());
+            parts.push(Part {
+                e_tag: e_tag.unwrap_or_default().to_string(),
+                length: end + 1 - start,
+            });
+        }
+        self.complete_multipart(to, &upload_id, &parts).await
+    }
+
+    async fn create_multipart(&self, id: &str) -> Result<String, RegistryError> {
+        let output = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(self.key(id))
+            .send()
+            .await
+            .map_err(s3_error)?;
+        output
+            .upload_id()
+            .map(|id| id.to_string())
+            .ok_or_else(|| RegistryError::Generic("S3 returned no upload id.".to_string()))
+    }
+
+    async fn complete_multipart(
+        &self,
+        id: &str,
+        upload_id: &str,
+        parts: &[Part],
+    ) -> Result<(), RegistryError> {
+        let parts = parts
+            .iter()
+            .enumerate()
+            .map(|(index, part)| {
+                CompletedPart::builder()
+                    .e_tag(&part.e_tag)
+                    .part_number(index as i32 + 1)
+                    .build()
+            })
+            .collect();
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(self.key(id))
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(s3_error)?;
+        Ok(())
+    }
+
+    async fn upload_part(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+        multipart: &mut Multipart,
+        content: Bytes,
+    ) -> Result<(), RegistryError> {
+        let data_id = blob_chunk_id(name, session_id);
+        let upload_id = match &multipart.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                // Kept straight away so the upload can be aborted with the session.
+                let upload_id = self.create_multipart(&data_id).await?;
+                multipart.upload_id = Some(upload_id.clone());
+                self.write_multipart(name, session_id, multipart).await?;
+                upload_id
+            }
+        };
+
+        let length = content.len();
+        let output = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(self.key(&data_id))
+            .upload_id(upload_id)
+            .part_number(multipart.parts.len() as i32 + 1)
+            .body(ByteStream::from(content))
+            .send()
+            .await
+            .map_err(s3_error)?;
+        multipart.parts.push(Part {
+            e_tag: output.e_tag().unwrap_or_default().to_string(),
+            length,
+        });
+        Ok(())
+    }
+
+    async fn write_multipart(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+        multipart: &Multipart,
+    ) -> Result<(), RegistryError> {
+        let data =
+            serde_json::to_vec(multipart).map_err(|e| RegistryError::Generic(e.to_string()))?;
+        self.write_object(&blob_chunk_parts_id(name, session_id), data)
+            .await
+    }
+
+    async fn read_multipart(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<Multipart, RegistryError> {
+        match self
+            .read_object(&blob_chunk_parts_id(name, session_id))
+            .await?
+        {
+            Some(data) => {
+                serde_json::from_slice(&data).map_err(|e| RegistryError::Generic(e.to_string()))
+            }
+            None => Ok(Multipart::default()),
+        }
+    }
+
+    async fn read_session_object(&self, id: &str) -> Result<Option<UploadSession>, RegistryError> {
+        if let Some(data) = self.read_object(id).await? {
+            return serde_json::from_slice(&data)
+                .map_err(|e| RegistryError::Generic(e.to_string()));
+        }
+
+        Ok(None)
+    }
+
+    // The tags object maps each tag in a repository to the digest of its manifest.
+    async fn read_tag_index(
+        &self,
+        name: &RepositoryName,
+    ) -> Result<BTreeMap<String, String>, RegistryError> {
+        match self.read_object(&tags_id(name)).await? {
+            Some(data) => {
+                serde_json::from_slice(&data).map_err(|e| RegistryError::Generic(e.to_string()))
+            }
+            None => Ok(BTreeMap::new()),
+        }
+    }
+
+    async fn write_tag_index(
+        &self,
+        name: &RepositoryName,
+        tags: &BTreeMap<String, String>,
+    ) -> Result<(), RegistryError> {
+        let data = serde_json::to_vec(tags).map_err(|e| RegistryError::Generic(e.to_string()))?;
+        self.write_object(&tags_id(name), data).await
+    }
+
+    async fn write_link(
+        &self,
+        name: &RepositoryName,
+        digest: &Digest,
+    ) -> Result<(), RegistryError> {
+        self.write_object(&blob_link_id(name, digest), digest.to_string())
+            .await
+    }
+}
+
+impl Multipart {
+    fn length(&self) -> usize {
+        self.parts.iter().map(|p| p.length).sum()
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3Store {
+    async fn read(
+        &self,
+        name: &RepositoryName,
+        digest: &Digest,
+    ) -> Result<Option<Blob>, RegistryError> {
+        if self
+            .object_length(&blob_link_id(name, digest))
+            .await?
+            .is_none()
+        {
+            return Ok(None);
+        }
+
+        Ok(self
+            .get(&blob_id(digest))
+            .await?
+            .map(|(body, content_length)| Blob {
+                metadata: BlobMetadata {
+                    digest: digest.clone(),
+                    content_length,
+                },
+                content: body_stream(body),
+            }))
+    }
+
+    async fn write_chunk(
+        &self,
+        name: &RepositoryName,
+        mut content: BlobStream,
+        session_id: &str,
+    ) -> Result<usize, RegistryError> {
+        // Content that does not fill a part is kept in a pending object
+        // until the next chunk, or the commit, tops it up. Parts are only
+        // recorded once the whole chunk has arrived, so a failed chunk is
+        // overwritten by the next attempt rather than appended to.
+        let pending_id = blob_chunk_pending_id(name, session_id);
+        let mut multipart = self.read_multipart(name, session_id).await?;
+        let mut buffer = BytesMut::new();
+        if let Some(pending) = self.read_object(&pending_id).await? {
+            buffer.extend_from_slice(&pending);
+        }
+
+        while let Some(bytes) = content.next().await {
+            buffer.extend_from_slice(&bytes?);
+            while buffer.len() >= PART_LENGTH {
+                let part = buffer.split_to(PART_LENGTH).freeze();
+                self.upload_part(name, session_id, &mut multipart, part)
+                    .await?;
+            }
+        }
+
+        let length = multipart.length() + buffer.len();
+        self.write_object(&pending_id, buffer.freeze()).await?;
+        self.write_multipart(name, session_id, &multipart).await?;
+        Ok(length)
+    }
+
+    async fn read_chunk(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<Option<BlobStream>, RegistryError> {
+        // Parts of an incomplete multipart upload cannot be read back.
+        if !self
+            .read_multipart(name, session_id)
+            .await?
+            .parts
+            .is_empty()
+        {
+            return Err(RegistryError::Unsupported);
+        }
+
+        Ok(self
+            .get(&blob_chunk_pending_id(name, session_id))
+            .await?
+            .map(|(body, _)| body_stream(body)))
+    }
+
+    async fn read_session(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<Option<UploadSession>, RegistryError> {
+        self.read_session_object(&blob_chunk_state_id(name, session_id))
+            .await
+    }
+
+    async fn write_session(
+        &self,
+        name: &RepositoryName,
+        session: &UploadSession,
+    ) -> Result<(), RegistryError> {
+        let data =
+            serde_json::to_vec(session).map_err(|e| RegistryError::Generic(e.to_string()))?;
+        self.write_object(&blob_chunk_state_id(name, &session.id), data)
+            .await
+    }
+
+    async fn commit_chunk(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+        digest: &Digest,
+    ) -> Result<(), RegistryError> {
+        let id = blob_id(digest);
+        if self.object_length(&id).await?.is_none() {
+            let pending = self
+                .read_object(&blob_chunk_pending_id(name, session_id))
+                .await?
+                .unwrap_or_default();
+            let mut multipart = self.read_multipart(name, session_id).await?;
+            if multipart.parts.is_empty() {
+                self.write_object(&id, pending).await?;
+            } else {
+                if !pending.is_empty() {
+                    self.upload_part(name, session_id, &mut multipart, pending)
+                        .await?;
+                }
+
+                // Uploads complete under the session, as the digest is only
+                // known once all content has arrived.
+                let data_id = blob_chunk_id(name, session_id);
+                let upload_id = multipart.upload_id.take().unwrap_or_default();
+                self.complete_multipart(&data_id, &upload_id, &multipart.parts)
+                    .await?;
+                self.copy_object(&data_id, &id, multipart.length()).await?;
+            }
+        }
+
+        self.write_link(name, digest).await?;
+        self.remove_session(name, session_id).await
+    }
+
+    async fn remove_session(
+        &self,
+        name: &RepositoryName,
+        session_id: &str,
+    ) -> Result<(), RegistryError> {
+        if let Some(upload_id) = self.read_multipart(name, session_id).await?.upload_id {
+            let result = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(self.key(&blob_chunk_id(name, session_id)))
+                .upload_id(upload_id)
+                .send()
+                .await;
+            // A committed session's upload has already been completed.
+            match result {
+                Err(e) if !e.as_service_error().is_some_and(|e| e.is_no_such_upload()) => {
+                    return Err(s3_error(e));
+                }
+                _ => {}
+            }
+        }
+
+        for id in self
+            .list_objects(&blob_chunk_session_id(name, session_id))
+            .await?
+        {
+            self.remove_object(&id).await?;
+        }
+        Ok(())
+    }
+
+    // Walks the names one level at a time, so shared blob content and each
+    // repository's own entries are never listed.
+    async fn list_sessions(&self) -> Result<Vec<UploadSession>, RegistryError> {
+        let mut sessions = vec![];
+        let mut dirs = vec![String::new()];
+        while let Some(dir) = dirs.pop() {
+            for child in self.list_dir(&dir).await?.0 {
+                let id = child_id(&dir, &child);
+                match child.as_str() {
+                    UPLOADS_DIR => {
+                        for session_id in self.list_dir(&id).await?.0 {
+                            let state_id = format!("{}/{}/session", id, session_id);
+                            if let Some(session) = self.read_session_object(&state_id).await? {
+                                sessions.push(session);
+                            }
+                        }
+                    }
+                    reserved if reserved.starts_with('_') => {}
+                    _ => dirs.push(id),
+                }
+            }
+        }
+        Ok(sessions)
+    }
+
+    async fn link(
+        &self,
+        from: &RepositoryName,
+        name: &RepositoryName,
+        digest: &Digest,
+    ) -> Result<bool, RegistryError> {
+        for id in [blob_link_id(from, digest), blob_id(digest)] {
+            if self.object_length(&id).await?.is_none() {
+                return Ok(false);
+            }
+        }
+
+        self.write_link(name, digest).await?;
+        Ok(true)
+    }
+
+    async fn remove(&self, name: &RepositoryName, digest: &Digest) -> Result<(), RegistryError> {
+        let id = blob_link_id(name, digest);
+        if self.object_length(&id).await?.is_none() {
+            return Err(RegistryError::BlobUnknown);
+        }
+
+        self.remove_object(&id).await
+    }
+}
+
+#[async_trait]
+impl ManifestStore for S3Store {
+    async fn read(
+        &self,
+        name: &RepositoryName,
+        reference: &Reference,
+    ) -> Result<Option<StoredManifest>, RegistryError> {
+        let digest = match reference {
+            Reference::Digest(digest) => digest.clone(),
+            Reference::Tag(tag) => match self.read_tag_index(name).await?.get(&tag.raw()) {
+                Some(digest) => Digest::new(digest)?,
+                None => return Ok(None),
+            },
+        };
+
+        // The media type is kept as the object's own Content-Type.
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(&manifest_id(name, &digest)))
+            .send()
+            .await;
+        match result {
+            Ok(output) => Ok(Some(StoredManifest {
+                media_type: output.content_type().map(|t| t.to_string()),
+                content: output.body.collect().await.map_err(s3_error)?.to_vec(),
+            })),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+            Err(e) => Err(s3_error(e)),
+        }
+    }
+
+    async fn write(
+        &self,
+        name: &RepositoryName,
+        digest: &Digest,
+        content: &[u8],
+        media_type: &str,
+    ) -> Result<(), RegistryError> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.key(&manifest_id(name, digest)))
+            .content_type(media_type)
+            .body(ByteStream::from(content.to_vec()))
+            .send()
+            .await
+            .map_err(s3_error)?;
+        Ok(())
+    }
+
+    async fn write_tag(
+        &self,
+        name: &RepositoryName,
+        tag: &Tag,
+        digest: &Digest,
+    ) -> Result<(), RegistryError> {
+        let mut tags = self.read_tag_index(name).await?;
+        tags.insert(tag.raw(), digest.to_string());
+        self.write_tag_index(name, &tags).await
+    }
+
+    async fn read_tags(&self, name: &RepositoryName) -> Result<Vec<Tag>, RegistryError> {
+        let mut output = vec![];
+        for raw_tag in self.read_tag_index(name).await?.keys() {
+            output.push(Tag::new(raw_tag)?);
+        }
+        Ok(output)
+    }
+
+    async fn read_repositories(&self) -> Result<Vec<String>, RegistryError> {
+        let mut repositories = vec![];
+        let mut dirs = vec![String::new()];
+        while let Some(dir) = dirs.pop() {
+            let (children, objects) = self.list_dir(&dir).await?;
+            let mut is_repository = objects.iter().any(|o| o == TAGS_OBJECT);
+            for child in children {
+                match child.as_str() {
+                    LINKS_DIR | MANIFESTS_DIR => is_repository = true,
+                    reserved if reserved.starts_with('_') => {}
+                    _ => dirs.push(child_id(&dir, &child)),
+                }
+            }
+            if is_repository {
+                repositories.push(dir);
+            }
+        }
+        repositories.sort();
+        Ok(repositories)
+    }
+
+    async fn remove(
+        &self,
+        name: &RepositoryName,
+        reference: &Reference,
+    ) -> Result<(), RegistryError> {
+        let mut tags = self.read_tag_index(name).await?;
+        match reference {
+            Reference::Digest(digest) => {
+                let id = manifest_id(name, digest);
+                if self.object_length(&id).await?.is_none() {
+                    return Err(RegistryError::ManifestUnknown);
+                }
+                self.remove_object(&id).await?;
+
+                let digest = digest.to_string();
+                let tag_count = tags.len();
+                tags.retain(|_, d| *d != digest);
+                if tags.len() == tag_count {
+                    return Ok(());
+                }
+            }
+            Reference::Tag(tag) => {
+                if tags.remove(&tag.raw()).is_none() {
+                    return Err(RegistryError::ManifestUnknown);
+                }
+            }
+        }
+
+        self.write_tag_index(name, &tags).await
+    }
+
+    async fn write_referrer(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+        referrer: &Descriptor,
+    ) -> Result<(), RegistryError> {
+        let referrer_digest = Digest::new(&referrer.digest)?;
+        let data =
+            serde_json::to_vec(referrer).map_err(|e| RegistryError::Generic(e.to_string()))?;
+        self.write_object(&referrer_id(name, subject, &referrer_digest), data)
+            .await
+    }
+
+    async fn read_referrers(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+    ) -> Result<Vec<Descriptor>, RegistryError> {
+        let mut referrers = vec![];
+        for id in self.list_objects(&referrers_id(name, subject)).await? {
+            if let Some(data) = self.read_object(&id).await? {
+                referrers.push(
+                    serde_json::from_slice::<Descriptor>(&data)
+                        .map_err(|e| RegistryError::Generic(e.to_string()))?,
+                );
+            }
+        }
+        referrers.sort_by(|a, b| a.digest.cmp(&b.digest));
+        Ok(referrers)
+    }
+
+    async fn remove_referrer(
+        &self,
+        name: &RepositoryName,
+        subject: &Digest,
+        referrer: &Digest,
+    ) -> Result<(), RegistryError> {
+        self.remove_object(&referrer_id(name, subject, referrer))
+            .await
+    }
+}
+
+fn s3_error(error: impl std::error::Error) -> RegistryError {
+    RegistryError::Generic(DisplayErrorContext(error).to_string())
+}
+
+fn body_stream(body: ByteStream) -> BlobStream {
+    Box::pin(stream::unfold(body, async |mut body| {
+        let bytes = body.next().await?;
+        Some((bytes.map_err(s3_error), body))
+    }))
+}
+
+fn manifest_id(name: &RepositoryName, digest: &Digest) -> String {
+    format!("{}/{}/{}", name.raw(), MANIFESTS_DIR, digest.hex())
+}
+
+fn tags_id(name: &RepositoryName) -> String {
+    format!("{}/{}", name.raw(), TAGS_OBJECT)
+}
+
+fn referrers_id(name: &RepositoryName, subject: &Digest) -> String {
+    format!("{}/{}/{}", name.raw(), REFERRERS_DIR, subject.hex())
+}
+
+fn referrer_id(name: &RepositoryName, subject: &Digest, referrer: &Digest) -> String {
+    format!("{}/{}", referrers_id(name, subject), referrer.hex())
+}
+
+fn blob_id(digest: &Digest) -> String {
+    format!("{}/{}/{}", BLOBS_DIR, digest.algorithm(), digest.hex())
+}
+
+fn blob_link_id(name: &RepositoryName, digest: &Digest) -> String {
+    format!("{}/{}/{}", name.raw(), LINKS_DIR, digest.hex())
+}
+
+fn blob_chunk_session_id(name: &RepositoryName, session_id: &str) -> String {
+    format!("{}/{}/{}", name.raw(), UPLOADS_DIR, session_id)
+}
+
+fn blob_chunk_id(name: &RepositoryName, session_id: &str) -> String {
+    format!("{}/data", blob_chunk_session_id(name, session_id))
+}
+
+fn blob_chunk_pending_id(name: &RepositoryName, session_id: &str) -> String {
+    format!("{}/pending", blob_chunk_session_id(name, session_id))
+}
+
+fn blob_chunk_parts_id(name: &RepositoryName, session_id: &str) -> String {
+    format!("{}/parts", blob_chunk_session_id(name, session_id))
+}
+
+fn blob_chunk_state_id(name: &RepositoryName, session_id: &str) -> String {
+    format!("{}/session", blob_chunk_session_id(name, session_id))
+}
+
+fn child_id(dir: &str, child: &str) -> String {
+    match dir {
+        "" => child.to_string(),
+        dir => format!("{}/{}", dir, child),
+    }
+}
+
+// Keys and common prefixes are listed in full, the name is what follows
+// `prefix`, without the trailing delimiter of a directory.
+fn entry_name<'a>(prefix: &str, key: &'a str) -> Option<&'a str> {
+    let name = key.strip_prefix(prefix)?.trim_end_matches('/');
+    (!name.is_empty()).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs against the bucket named by REGGY_S3_TEST_BUCKET at REGGY_S3_TEST_ENDPOINT,
+    // see the README for running them on a local MinIO.
+    async fn store() -> ((), S3Store) {
+        let bucket = std::env::var("REGGY_S3_TEST_BUCKET").unwrap_or("reggy".to_string());
+        let endpoint = std::env::var("REGGY_S3_TEST_ENDPOINT").ok();
+        let prefix = uuid::Uuid::new_v4().to_string();
+        let store =
+            S3Store::connect(&bucket, &prefix, endpoint.as_deref(), Some("us-east-1")).await;
+        ((), store)
+    }
+
+    reggy_core::storage_conformance_tests!(
+        store().await,
+        #[ignore = "needs an S3 endpoint"]
+    );
+
+    #[test]
+    fn names_entries_below_a_prefix() {
+        assert_eq!(entry_name("root/team/", "root/team/app/"), Some("app"));
+        assert_eq!(entry_name("root/team/", "root/team/_tags"), Some("_tags"));
+        assert_eq!(entry_name("root/team/", "root/team/"), None);
+        assert_eq!(entry_name("root/team/", "root/other/app/"), None);
+        assert_eq!(child_id("", "team"), "team");
+        assert_eq!(child_id("team", "app"), "team/app");
+    }
+}